@@ -1,6 +1,8 @@
-pub(crate) const STORAGE_PREFIX: &'static str = "bench2";
+pub(crate) const STORAGE_PREFIX: &str = "bench2";
 pub(crate) const QUEUE_SIZE: usize = 10_000_000;
 pub(crate) const MESSAGE_SIZE: usize = 50;
 pub(crate) const MESSAGES_COUNT: usize = 10_000_000;
 #[allow(dead_code)]
 pub(crate) const PUSH_DELAY: u64 = 0;
+#[allow(dead_code)]
+pub(crate) const TIMESTAMPS: bool = true;
@@ -0,0 +1,74 @@
+use jemallocator::Jemalloc;
+use native_ipc_rust::{Layout, Reader, Writer, WriterOptions};
+use std::{
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+#[global_allocator]
+static GLOBAL: Jemalloc = Jemalloc;
+
+// Every layout is run against a reader spinning on another thread:
+//
+//   cargo run --release --example layout
+//
+// Segments have the same header whatever the layout, so Packed isn't the
+// header as it was before layouts existed: it carries the padded cursors
+// as well and only differs in which of the cursors are used
+const QUEUE_SIZE: usize = 1 << 24;
+const MESSAGE_SIZE: usize = 50;
+const MESSAGES_COUNT: usize = 5_000_000;
+
+fn run(layout: Layout) {
+    let prefix = format!("layout-{:?}-{}", layout, std::process::id());
+    let (done_tx, done_rx) = mpsc::channel();
+
+    let writer_thread = {
+        let prefix = prefix.clone();
+        std::thread::spawn(move || {
+            let options = WriterOptions {
+                timestamps: true,
+                layout,
+                ..WriterOptions::default()
+            };
+            let mut writer = Writer::<QUEUE_SIZE>::with_options(prefix, options).unwrap();
+            let message = [b'a'; MESSAGE_SIZE];
+            for _ in 0..MESSAGES_COUNT {
+                writer.ipc_push(&message).unwrap();
+            }
+            // segments are unlinked once the writer is gone
+            done_rx.recv().unwrap();
+        })
+    };
+
+    let mut reader = Reader::<QUEUE_SIZE>::connect_wait(&prefix, Duration::from_secs(5)).unwrap();
+    let started_at = Instant::now();
+    let mut popped = 0;
+    while popped < MESSAGES_COUNT {
+        if reader.pop_ref().unwrap().is_some() {
+            popped += 1;
+        }
+    }
+    let elapsed = started_at.elapsed().as_secs_f64();
+
+    done_tx.send(()).unwrap();
+    writer_thread.join().unwrap();
+
+    let latency = reader.latency();
+    println!(
+        "{:<10} {:>12.0} msg/s {:>10.1} MB/s   p50 {:>8?} p99 {:>8?} p99.9 {:>8?} max {:>8?}",
+        format!("{:?}", layout),
+        MESSAGES_COUNT as f64 / elapsed,
+        (MESSAGES_COUNT * MESSAGE_SIZE) as f64 / elapsed / 1e6,
+        latency.p50,
+        latency.p99,
+        latency.p999,
+        latency.max,
+    );
+}
+
+fn main() {
+    for layout in [Layout::Packed, Layout::Aligned8, Layout::Aligned64] {
+        run(layout);
+    }
+}
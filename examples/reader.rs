@@ -13,7 +13,8 @@ fn main() {
     let started_at = Instant::now();
 
     for _ in 0..config::MESSAGES_COUNT {
-        if let Some(message) = reader.ipc_pop().unwrap() {
+        // borrowed in place, nothing is allocated per message
+        if let Some(message) = reader.pop_ref().unwrap() {
             assert_eq!(message.len(), config::MESSAGE_SIZE);
             // println!(
             //     "message {} {:?}",
@@ -34,5 +35,13 @@ fn main() {
         total_bytes as f64 / diff
     );
 
+    let latency = reader.latency();
+    if latency.count > 0 {
+        println!(
+            "Latency: p50 {:?}, p99 {:?}, p999 {:?}, max {:?}",
+            latency.p50, latency.p99, latency.p999, latency.max
+        );
+    }
+
     println!("Done");
 }
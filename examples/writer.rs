@@ -1,6 +1,6 @@
 use jemallocator::Jemalloc;
 use libc::{signal, SIGINT};
-use native_ipc_rust::Writer;
+use native_ipc_rust::{Writer, WriterOptions};
 use rand::{seq::SliceRandom, thread_rng};
 use std::time::Instant;
 
@@ -28,9 +28,14 @@ fn random_message(size: usize) -> Vec<u8> {
 }
 
 fn main() {
-    let mut writer = Writer::<{ config::QUEUE_SIZE }>::new(config::STORAGE_PREFIX).unwrap();
+    let options = WriterOptions {
+        timestamps: config::TIMESTAMPS,
+        ..WriterOptions::default()
+    };
+    let mut writer =
+        Writer::<{ config::QUEUE_SIZE }>::with_options(config::STORAGE_PREFIX, options).unwrap();
 
-    unsafe { signal(SIGINT, on_interrupt as usize) };
+    unsafe { signal(SIGINT, on_interrupt as *const () as usize) };
 
     let started_at = Instant::now();
 
@@ -0,0 +1,308 @@
+use std::{
+    error::Error,
+    io::{self, BufRead, Write},
+    time::Duration,
+};
+
+use native_ipc_rust::{
+    list_channels, purge_channel, shm_space, ChannelState, Observer, Position, QueueState, Writer,
+};
+
+const USAGE: &str = "usage:
+    shmipc list                              channels and segments in /dev/shm
+    shmipc show <prefix>                     header and cursor state
+    shmipc dump <prefix> [--hex]             messages that aren't popped yet
+    shmipc tail <prefix> [--hex] [--all]     follow a channel without consuming it
+    shmipc push <prefix> [--queue-size N]    push every line of stdin as a message
+    shmipc purge <prefix>                    unlink every segment of a channel";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let result = match args.as_slice() {
+        ["list"] => list(),
+        ["show", prefix] => show(prefix),
+        ["dump", prefix, flags @ ..] => dump(prefix, flags),
+        ["tail", prefix, flags @ ..] => tail(prefix, flags),
+        ["push", prefix, flags @ ..] => push(prefix, flags),
+        ["purge", prefix] => purge(prefix),
+        _ => Err(USAGE.into()),
+    };
+
+    if let Err(err) = result {
+        // `shmipc tail ... | head` is not a failure
+        let broken_pipe = err
+            .downcast_ref::<io::Error>()
+            .is_some_and(|err| err.kind() == io::ErrorKind::BrokenPipe);
+        if !broken_pipe {
+            let mut message = err.to_string();
+            let mut source = err.source();
+            while let Some(err) = source {
+                message = format!("{}: {}", message, err);
+                source = err.source();
+            }
+            eprintln!("shmipc: {}", message);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn list() -> Result<()> {
+    let mut out = io::stdout().lock();
+    writeln!(
+        out,
+        "{:<32} {:<48} {:>12} OWNER",
+        "PREFIX", "SEGMENT", "SIZE"
+    )?;
+    for channel in list_channels()? {
+        let segments = channel
+            .root
+            .iter()
+            .chain(channel.workers.iter().map(|(_, info)| info))
+            .chain(channel.slab.iter())
+            .chain(channel.pod.iter())
+            .chain(channel.blobs.iter());
+        for info in segments {
+            writeln!(
+                out,
+                "{:<32} {:<48} {:>12} {}",
+                channel.prefix,
+                info.name,
+                info.size,
+                owner(info.uid)
+            )?;
+        }
+        if channel.root.is_none() {
+            writeln!(
+                out,
+                "{:<32} (no root segment, writer is gone)",
+                channel.prefix
+            )?;
+        }
+    }
+    let space = shm_space()?;
+    writeln!(
+        out,
+        "\n{} of {} bytes free in /dev/shm",
+        space.free, space.total
+    )?;
+    Ok(())
+}
+
+fn show(prefix: &str) -> Result<()> {
+    let state = Observer::attach(prefix)?.state()?;
+    print_state(&mut io::stdout().lock(), prefix, &state)?;
+    Ok(())
+}
+
+fn dump(prefix: &str, flags: &[&str]) -> Result<()> {
+    let hex = parse_flags(flags, &["--hex"])?.contains(&"--hex");
+    let mut out = io::stdout().lock();
+    for message in Observer::attach(prefix)?.pending()? {
+        print_message(&mut out, &message, hex)?;
+    }
+    Ok(())
+}
+
+fn tail(prefix: &str, flags: &[&str]) -> Result<()> {
+    let flags = parse_flags(flags, &["--hex", "--all"])?;
+    let hex = flags.contains(&"--hex");
+
+    let mut observer = Observer::attach(prefix)?;
+    let mut position = if flags.contains(&"--all") {
+        Position::default()
+    } else {
+        observer.end()?
+    };
+
+    let mut out = io::stdout().lock();
+    loop {
+        for message in observer.read_from(&mut position)? {
+            print_message(&mut out, &message, hex)?;
+        }
+        out.flush()?;
+
+        if observer.is_detached()? {
+            eprintln!("shmipc: writer is gone, waiting for it to come back");
+            observer = loop {
+                std::thread::sleep(POLL_INTERVAL);
+                if let Ok(observer) = Observer::attach(prefix) {
+                    break observer;
+                }
+            };
+            // a new writer starts a new channel from scratch
+            position = Position::default();
+            continue;
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn push(prefix: &str, flags: &[&str]) -> Result<()> {
+    let queue_size = match flags {
+        [] => 10_000_000,
+        ["--queue-size", size] => size.parse()?,
+        _ => return Err(USAGE.into()),
+    };
+
+    // the size is a const parameter, so only the common ones are supported
+    match queue_size {
+        1_000 => push_lines::<1_000>(prefix),
+        10_000 => push_lines::<10_000>(prefix),
+        100_000 => push_lines::<100_000>(prefix),
+        1_000_000 => push_lines::<1_000_000>(prefix),
+        10_000_000 => push_lines::<10_000_000>(prefix),
+        _ => Err(format!(
+            "unsupported queue size {}, use 1000, 10000, 100000, 1000000 or 10000000",
+            queue_size
+        )
+        .into()),
+    }
+}
+
+fn push_lines<const QUEUE_SIZE: usize>(prefix: &str) -> Result<()> {
+    let mut writer = Writer::<QUEUE_SIZE>::new(prefix)?;
+
+    for line in io::stdin().lock().lines() {
+        writer.ipc_push(line?.as_bytes())?;
+    }
+
+    // the segments are unlinked on shutdown, give an attached reader a chance to drain them
+    let observer = Observer::attach(prefix)?;
+    loop {
+        let state = observer.state()?;
+        if state.reader_pid == 0 || state.stats.depth_messages == 0 {
+            break;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+
+    writer.shutdown()?;
+    Ok(())
+}
+
+fn purge(prefix: &str) -> Result<()> {
+    let purged = purge_channel(prefix)?;
+    if purged.is_empty() {
+        return Err(format!("no segments found for {}", prefix).into());
+    }
+    for name in purged {
+        println!("unlinked {}", name);
+    }
+    Ok(())
+}
+
+fn parse_flags<'a>(flags: &[&'a str], known: &[&str]) -> Result<Vec<&'a str>> {
+    match flags.iter().find(|flag| !known.contains(flag)) {
+        Some(flag) => Err(format!("unknown option {}\n{}", flag, USAGE).into()),
+        None => Ok(flags.to_vec()),
+    }
+}
+
+fn owner(uid: u32) -> String {
+    let passwd = unsafe { libc::getpwuid(uid) };
+    if passwd.is_null() {
+        return uid.to_string();
+    }
+    unsafe { std::ffi::CStr::from_ptr((*passwd).pw_name) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn print_state(out: &mut impl Write, prefix: &str, state: &ChannelState) -> io::Result<()> {
+    let stats = &state.stats;
+    writeln!(out, "channel        {}", prefix)?;
+    writeln!(out, "generation     {}", state.generation)?;
+    if state.type_fingerprint != 0 {
+        writeln!(out, "type           {:016x}", state.type_fingerprint)?;
+    }
+    writeln!(out, "writer pid     {}", pid(state.writer_pid))?;
+    writeln!(out, "reader pid     {}", pid(state.reader_pid))?;
+    writeln!(
+        out,
+        "pushed         {} messages, {} bytes",
+        stats.messages_pushed, stats.bytes_pushed
+    )?;
+    writeln!(
+        out,
+        "popped         {} messages, {} bytes",
+        stats.messages_popped, stats.bytes_popped
+    )?;
+    writeln!(
+        out,
+        "depth          {} messages, {} bytes",
+        stats.depth_messages, stats.depth_bytes
+    )?;
+    writeln!(
+        out,
+        "segments       writer at {}, reader at {}, {} rotations",
+        stats.writer_segment, stats.reader_segment, stats.rotations
+    )?;
+    writeln!(out, "announcements  {}", cursors(&state.announcements))?;
+    for segment in &state.segments {
+        writeln!(
+            out,
+            "{:<48} epoch {:<6} {} bytes  {}",
+            segment.name,
+            segment.epoch,
+            segment.size,
+            cursors(&segment.queue)
+        )?;
+    }
+    Ok(())
+}
+
+fn pid(pid: u32) -> String {
+    match pid {
+        0 => "-".to_string(),
+        pid => pid.to_string(),
+    }
+}
+
+fn cursors(queue: &QueueState) -> String {
+    let mut flags = vec![];
+    if queue.done_writing {
+        flags.push("done writing");
+    }
+    if queue.done_reading {
+        flags.push("done reading");
+    }
+    format!(
+        "start {} end {}, {} pending ({} bytes){}{}",
+        queue.start,
+        queue.end,
+        queue.pending_messages,
+        queue.pending_bytes,
+        if flags.is_empty() { "" } else { ", " },
+        flags.join(", ")
+    )
+}
+
+fn print_message(out: &mut impl Write, message: &[u8], hex: bool) -> io::Result<()> {
+    if !hex {
+        return writeln!(out, "{}", String::from_utf8_lossy(message));
+    }
+
+    writeln!(out, "{} bytes", message.len())?;
+    for (i, chunk) in message.chunks(16).enumerate() {
+        let bytes: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let text: String = chunk
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        writeln!(out, "  {:08x}  {:<47}  |{}|", i * 16, bytes.join(" "), text)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,252 @@
+use std::io;
+
+use libc::{
+    MAP_SHARED, O_CREAT, O_EXCL, O_RDONLY, O_RDWR, PROT_READ, PROT_WRITE, S_IRUSR, S_IWUSR,
+};
+
+use crate::{
+    capi::{close, fstat, mmap, munmap, shm_open, shm_unlink},
+    event::event,
+    writer::allocate,
+    ConnectionType, ReaderConnectError, WriterConnectError,
+};
+
+// A payload too large for the queues is put in a segment of its own
+// (see `Writer::push_large`), only its descriptor goes through the queue:
+//
+//   length: u64 | checksum: u64
+//
+// The segment is named after the writer generation and the sequence number
+// of the message. The reader unlinks it as soon as it's mapped, the memory
+// goes away with the mapping. Whatever the reader hasn't picked up
+// is unlinked by the writer when it shuts down
+pub(crate) const DESCRIPTOR_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Descriptor {
+    pub(crate) len: usize,
+    pub(crate) checksum: u64,
+}
+
+impl Descriptor {
+    pub(crate) fn encode(&self) -> [u8; DESCRIPTOR_LEN] {
+        let mut buffer = [0; DESCRIPTOR_LEN];
+        buffer[..8].copy_from_slice(&(self.len as u64).to_le_bytes());
+        buffer[8..].copy_from_slice(&self.checksum.to_le_bytes());
+        buffer
+    }
+
+    pub(crate) fn decode(payload: &[u8]) -> Option<Self> {
+        let payload: &[u8; DESCRIPTOR_LEN] = payload.try_into().ok()?;
+        Some(Self {
+            len: u64::from_le_bytes(payload[..8].try_into().unwrap()) as usize,
+            checksum: u64::from_le_bytes(payload[8..].try_into().unwrap()),
+        })
+    }
+}
+
+// FNV-1a, it's only there to catch a segment that isn't what was announced
+pub(crate) fn checksum(payload: &[u8]) -> u64 {
+    payload.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// Creates the segment with `payload` in it, nothing is kept mapped
+pub(crate) fn create(
+    connection_type: &ConnectionType,
+    payload: &[u8],
+) -> Result<Descriptor, WriterConnectError> {
+    // left behind by a writer that crashed with the same generation, very unlikely
+    let _ = shm_unlink(connection_type.id());
+
+    let fd = shm_open(
+        connection_type.id(),
+        O_RDWR | O_CREAT | O_EXCL,
+        (S_IRUSR | S_IWUSR) as std::ffi::c_uint,
+    )
+    .map_err(|source| WriterConnectError::ShmOpenError {
+        segment: connection_type.name(),
+        source,
+    })?;
+
+    let written = write(fd, connection_type, payload);
+    let _ = close(fd);
+    if let Err(err) = written {
+        let _ = shm_unlink(connection_type.id());
+        return Err(err);
+    }
+
+    event!(
+        debug,
+        "writer.blob_created",
+        segment = connection_type.name(),
+        size = payload.len(),
+    );
+
+    Ok(Descriptor {
+        len: payload.len(),
+        checksum: checksum(payload),
+    })
+}
+
+fn write(
+    fd: i32,
+    connection_type: &ConnectionType,
+    payload: &[u8],
+) -> Result<(), WriterConnectError> {
+    allocate(fd, payload.len(), connection_type)?;
+    // an empty mapping is an error
+    if payload.is_empty() {
+        return Ok(());
+    }
+
+    let addr = mmap(
+        std::ptr::null_mut(),
+        payload.len(),
+        PROT_WRITE,
+        MAP_SHARED,
+        fd,
+        0,
+    )
+    .map_err(|source| WriterConnectError::MmapError {
+        segment: connection_type.name(),
+        source,
+    })?;
+    unsafe {
+        std::ptr::copy_nonoverlapping(payload.as_ptr(), addr.cast::<u8>(), payload.len());
+    }
+    let _ = munmap(addr, payload.len());
+    Ok(())
+}
+
+// Unlinks a segment nobody has picked up, it's fine if it's gone already
+pub(crate) fn unlink(connection_type: &ConnectionType) -> io::Result<bool> {
+    match shm_unlink(connection_type.id()) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+// A mapped payload, read only. The segment is unlinked as soon as
+// it's mapped, so it's gone for good once this is dropped
+#[derive(Debug)]
+pub(crate) struct Blob {
+    addr: *mut std::ffi::c_void,
+    len: usize,
+}
+
+impl Blob {
+    pub(crate) fn open(
+        connection_type: &ConnectionType,
+        len: usize,
+    ) -> Result<Self, ReaderConnectError> {
+        let fd = shm_open(
+            connection_type.id(),
+            O_RDONLY,
+            (S_IRUSR | S_IWUSR) as std::ffi::c_uint,
+        )
+        .map_err(|source| ReaderConnectError::ShmOpenError {
+            segment: connection_type.name(),
+            source,
+        })?;
+        let _ = shm_unlink(connection_type.id());
+
+        let mapped = Self::map(fd, connection_type, len);
+        let _ = close(fd);
+        mapped
+    }
+
+    fn map(
+        fd: i32,
+        connection_type: &ConnectionType,
+        len: usize,
+    ) -> Result<Self, ReaderConnectError> {
+        let stat = fstat(fd).map_err(|source| ReaderConnectError::FstatError {
+            segment: connection_type.name(),
+            source,
+        })?;
+        if stat.st_size != len as i64 {
+            return Err(ReaderConnectError::Uninitialized {
+                segment: connection_type.name(),
+            });
+        }
+        if len == 0 {
+            return Ok(Self {
+                addr: std::ptr::null_mut(),
+                len,
+            });
+        }
+
+        let addr =
+            mmap(std::ptr::null_mut(), len, PROT_READ, MAP_SHARED, fd, 0).map_err(|source| {
+                ReaderConnectError::MmapError {
+                    segment: connection_type.name(),
+                    source,
+                }
+            })?;
+        Ok(Self { addr, len })
+    }
+
+    pub(crate) fn data(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.addr.cast::<u8>(), self.len) }
+    }
+}
+
+impl Drop for Blob {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            let _ = munmap(self.addr, self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let connection_type = ConnectionType::random();
+        let payload: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+
+        let descriptor = create(&connection_type, &payload).unwrap();
+        assert_eq!(Descriptor::decode(&descriptor.encode()), Some(descriptor));
+        assert_eq!(descriptor.len, payload.len());
+
+        let blob = Blob::open(&connection_type, descriptor.len).unwrap();
+        assert_eq!(blob.data(), payload);
+        assert_eq!(checksum(blob.data()), descriptor.checksum);
+        // unlinked once it's mapped
+        assert!(!unlink(&connection_type).unwrap());
+
+        let err = Blob::open(&connection_type, descriptor.len).unwrap_err();
+        assert_eq!(
+            err.io_error().map(io::Error::kind),
+            Some(io::ErrorKind::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        let connection_type = ConnectionType::random();
+
+        let descriptor = create(&connection_type, b"").unwrap();
+        let blob = Blob::open(&connection_type, descriptor.len).unwrap();
+        assert_eq!(blob.data(), b"");
+        assert_eq!(descriptor.checksum, checksum(b""));
+    }
+
+    #[test]
+    fn test_wrong_len() {
+        let connection_type = ConnectionType::random();
+
+        create(&connection_type, b"123").unwrap();
+        let err = Blob::open(&connection_type, 4).unwrap_err();
+        assert!(matches!(err, ReaderConnectError::Uninitialized { .. }));
+    }
+}
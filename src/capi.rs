@@ -1,16 +1,13 @@
-use std::ffi::{c_int, c_uint, c_void, CStr};
+use std::{
+    ffi::{c_int, c_uint, c_void, CStr},
+    io::Error,
+};
 
-pub(crate) fn strerror(code: c_int) -> &'static str {
-    let err = unsafe { libc::strerror(code) };
-    let s = unsafe { std::ffi::CStr::from_ptr(err) };
-    s.to_str().unwrap_or("Failed to get error")
+fn errno() -> Error {
+    Error::last_os_error()
 }
 
-pub(crate) fn errno() -> Option<i32> {
-    std::io::Error::last_os_error().raw_os_error()
-}
-
-pub(crate) fn shm_open(name: &CStr, oflag: c_int, mode: c_uint) -> Result<i32, Option<i32>> {
+pub(crate) fn shm_open(name: &CStr, oflag: c_int, mode: c_uint) -> Result<i32, Error> {
     let fd = unsafe { libc::shm_open(name.as_ptr(), oflag, mode) };
     if fd == -1 {
         Err(errno())
@@ -19,7 +16,7 @@ pub(crate) fn shm_open(name: &CStr, oflag: c_int, mode: c_uint) -> Result<i32, O
     }
 }
 
-pub(crate) fn ftruncate(fd: c_int, length: i64) -> Result<(), Option<i32>> {
+pub(crate) fn ftruncate(fd: c_int, length: i64) -> Result<(), Error> {
     let res = unsafe { libc::ftruncate(fd, length) };
     if res == -1 {
         Err(errno())
@@ -35,7 +32,7 @@ pub(crate) fn mmap(
     flags: c_int,
     fd: c_int,
     offset: i64,
-) -> Result<*mut c_void, Option<i32>> {
+) -> Result<*mut c_void, Error> {
     let addr = unsafe { libc::mmap(addr, length, protection, flags, fd, offset) };
     if addr == libc::MAP_FAILED {
         Err(errno())
@@ -44,7 +41,7 @@ pub(crate) fn mmap(
     }
 }
 
-pub(crate) fn munmap(addr: *mut c_void, length: usize) -> Result<(), Option<i32>> {
+pub(crate) fn munmap(addr: *mut c_void, length: usize) -> Result<(), Error> {
     let code = unsafe { libc::munmap(addr, length) };
     if code == -1 {
         Err(errno())
@@ -53,7 +50,7 @@ pub(crate) fn munmap(addr: *mut c_void, length: usize) -> Result<(), Option<i32>
     }
 }
 
-pub(crate) fn shm_unlink(name: &CStr) -> Result<(), Option<i32>> {
+pub(crate) fn shm_unlink(name: &CStr) -> Result<(), Error> {
     let code = unsafe { libc::shm_unlink(name.as_ptr()) };
     if code == -1 {
         Err(errno())
@@ -61,3 +58,237 @@ pub(crate) fn shm_unlink(name: &CStr) -> Result<(), Option<i32>> {
         Ok(())
     }
 }
+
+pub(crate) fn fstat(fd: c_int) -> Result<libc::stat, Error> {
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    let code = unsafe { libc::fstat(fd, stat.as_mut_ptr()) };
+    if code == -1 {
+        Err(errno())
+    } else {
+        Ok(unsafe { stat.assume_init() })
+    }
+}
+
+// CLOCK_MONOTONIC is system-wide, so timestamps taken by the writer
+// can be compared with the reader's clock. It can't fail for a valid clock
+pub(crate) fn monotonic_nanos() -> u64 {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time) };
+    time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
+}
+
+pub(crate) fn close(fd: c_int) -> Result<(), Error> {
+    let code = unsafe { libc::close(fd) };
+    if code == -1 {
+        Err(errno())
+    } else {
+        Ok(())
+    }
+}
+
+pub(crate) fn inotify_init1(flags: c_int) -> Result<i32, Error> {
+    let fd = unsafe { libc::inotify_init1(flags) };
+    if fd == -1 {
+        Err(errno())
+    } else {
+        Ok(fd)
+    }
+}
+
+pub(crate) fn inotify_add_watch(fd: c_int, path: &CStr, mask: u32) -> Result<i32, Error> {
+    let wd = unsafe { libc::inotify_add_watch(fd, path.as_ptr(), mask) };
+    if wd == -1 {
+        Err(errno())
+    } else {
+        Ok(wd)
+    }
+}
+
+pub(crate) fn poll_readable(fd: c_int, timeout_ms: c_int) -> Result<bool, Error> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let code = unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+    if code == -1 {
+        Err(errno())
+    } else {
+        Ok(code > 0)
+    }
+}
+
+pub(crate) fn drain(fd: c_int) {
+    let mut buf = [0_u8; 4096];
+    while unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) } > 0 {}
+}
+
+pub(crate) fn mlock(addr: *const c_void, length: usize) -> Result<(), Error> {
+    let code = unsafe { libc::mlock(addr, length) };
+    if code == -1 {
+        Err(errno())
+    } else {
+        Ok(())
+    }
+}
+
+pub(crate) fn madvise(addr: *mut c_void, length: usize, advice: c_int) -> Result<(), Error> {
+    let code = unsafe { libc::madvise(addr, length, advice) };
+    if code == -1 {
+        Err(errno())
+    } else {
+        Ok(())
+    }
+}
+
+pub(crate) fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+// Unlike most calls it returns the error instead of setting errno
+pub(crate) fn posix_fallocate(fd: c_int, offset: i64, length: i64) -> Result<(), Error> {
+    let code = unsafe { libc::posix_fallocate(fd, offset, length) };
+    if code != 0 {
+        Err(Error::from_raw_os_error(code))
+    } else {
+        Ok(())
+    }
+}
+
+pub(crate) fn statvfs(path: &CStr) -> Result<libc::statvfs, Error> {
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    let code = unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) };
+    if code == -1 {
+        Err(errno())
+    } else {
+        Ok(unsafe { stat.assume_init() })
+    }
+}
+
+// kill(pid, 0) checks the process is there without signalling it,
+// EPERM means it is, but belongs to somebody else
+pub(crate) fn process_exists(pid: u32) -> bool {
+    let pid = match libc::pid_t::try_from(pid) {
+        Ok(pid) if pid > 0 => pid,
+        _ => return false,
+    };
+    let code = unsafe { libc::kill(pid, 0) };
+    code == 0 || errno().raw_os_error() == Some(libc::EPERM)
+}
+
+fn sockaddr_un(path: &CStr) -> Result<(libc::sockaddr_un, libc::socklen_t), Error> {
+    let mut addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    let bytes = path.to_bytes();
+    // room for the trailing nul
+    if bytes.len() >= addr.sun_path.len() {
+        return Err(Error::from_raw_os_error(libc::ENAMETOOLONG));
+    }
+    for (dst, src) in addr.sun_path.iter_mut().zip(bytes) {
+        *dst = *src as libc::c_char;
+    }
+    let len = std::mem::size_of::<libc::sa_family_t>() + bytes.len() + 1;
+    Ok((addr, len as libc::socklen_t))
+}
+
+pub(crate) fn socket(kind: c_int) -> Result<i32, Error> {
+    let fd = unsafe { libc::socket(libc::AF_UNIX, kind | libc::SOCK_CLOEXEC, 0) };
+    if fd == -1 {
+        Err(errno())
+    } else {
+        Ok(fd)
+    }
+}
+
+pub(crate) fn bind(fd: c_int, path: &CStr) -> Result<(), Error> {
+    let (addr, len) = sockaddr_un(path)?;
+    let code = unsafe { libc::bind(fd, (&addr as *const libc::sockaddr_un).cast(), len) };
+    if code == -1 {
+        Err(errno())
+    } else {
+        Ok(())
+    }
+}
+
+pub(crate) fn listen(fd: c_int, backlog: c_int) -> Result<(), Error> {
+    let code = unsafe { libc::listen(fd, backlog) };
+    if code == -1 {
+        Err(errno())
+    } else {
+        Ok(())
+    }
+}
+
+pub(crate) fn accept(fd: c_int) -> Result<i32, Error> {
+    let fd = unsafe {
+        libc::accept4(
+            fd,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            libc::SOCK_CLOEXEC,
+        )
+    };
+    if fd == -1 {
+        Err(errno())
+    } else {
+        Ok(fd)
+    }
+}
+
+pub(crate) fn connect(fd: c_int, path: &CStr) -> Result<(), Error> {
+    let (addr, len) = sockaddr_un(path)?;
+    let code = unsafe { libc::connect(fd, (&addr as *const libc::sockaddr_un).cast(), len) };
+    if code == -1 {
+        Err(errno())
+    } else {
+        Ok(())
+    }
+}
+
+// Never blocks, nor raises SIGPIPE once the peer is gone
+pub(crate) fn send_vectored(fd: c_int, parts: &[std::io::IoSlice<'_>]) -> Result<usize, Error> {
+    let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+    header.msg_iov = parts.as_ptr() as *mut libc::iovec;
+    header.msg_iovlen = parts.len() as _;
+    let sent = unsafe { libc::sendmsg(fd, &header, libc::MSG_DONTWAIT | libc::MSG_NOSIGNAL) };
+    if sent == -1 {
+        Err(errno())
+    } else {
+        Ok(sent as usize)
+    }
+}
+
+// Length of the next packet without taking it, 0 once the peer is gone
+pub(crate) fn peek_len(fd: c_int) -> Result<usize, Error> {
+    let len = unsafe {
+        libc::recv(
+            fd,
+            std::ptr::null_mut(),
+            0,
+            libc::MSG_PEEK | libc::MSG_TRUNC | libc::MSG_DONTWAIT,
+        )
+    };
+    if len == -1 {
+        Err(errno())
+    } else {
+        Ok(len as usize)
+    }
+}
+
+pub(crate) fn recv_vectored(
+    fd: c_int,
+    parts: &mut [std::io::IoSliceMut<'_>],
+) -> Result<usize, Error> {
+    let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+    header.msg_iov = parts.as_mut_ptr().cast();
+    header.msg_iovlen = parts.len() as _;
+    let received = unsafe { libc::recvmsg(fd, &mut header, libc::MSG_DONTWAIT) };
+    if received == -1 {
+        Err(errno())
+    } else {
+        Ok(received as usize)
+    }
+}
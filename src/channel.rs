@@ -0,0 +1,168 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+// Channel-wide state, kept in the header of the root segment so that
+// both sides (and anyone else who maps it) can tell who is attached
+// and how far behind the reader is. Worker segments carry this header
+// too, but leave it zeroed.
+//
+// Every counter has a single writing side, so they are updated
+// with plain load/store instead of locked read-modify-write.
+// They're bumped on every push and pop, so each side's counters
+// are on a cache line of their own
+#[repr(C)]
+#[derive(Debug, Default)]
+pub(crate) struct ChannelHeader {
+    writer_pid: AtomicU32,
+    reader_pid: AtomicU32,
+    // what the messages are, see `TypedWriter`, 0 for plain bytes
+    type_fingerprint: AtomicU64,
+
+    writer: WriterCounters,
+    reader: ReaderCounters,
+}
+
+#[repr(C, align(64))]
+#[derive(Debug, Default)]
+struct WriterCounters {
+    messages_pushed: AtomicU64,
+    bytes_pushed: AtomicU64,
+    rotations: AtomicU64,
+    writer_segment: AtomicU64,
+}
+
+#[repr(C, align(64))]
+#[derive(Debug, Default)]
+struct ReaderCounters {
+    messages_popped: AtomicU64,
+    bytes_popped: AtomicU64,
+    reader_segment: AtomicU64,
+}
+
+fn bump(counter: &AtomicU64, by: u64) {
+    counter.store(counter.load(Ordering::Relaxed) + by, Ordering::Relaxed);
+}
+
+impl ChannelHeader {
+    pub(crate) fn set_writer_pid(&self, pid: u32) {
+        self.writer_pid.store(pid, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_reader_pid(&self) {
+        self.reader_pid.store(std::process::id(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn clear_reader_pid(&self) {
+        let _ = self.reader_pid.compare_exchange(
+            std::process::id(),
+            0,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+    }
+
+    pub(crate) fn set_type_fingerprint(&self, fingerprint: u64) {
+        self.type_fingerprint.store(fingerprint, Ordering::Relaxed);
+    }
+
+    pub(crate) fn type_fingerprint(&self) -> u64 {
+        self.type_fingerprint.load(Ordering::Relaxed)
+    }
+
+    // 0 if nobody is attached
+    pub(crate) fn writer_pid(&self) -> u32 {
+        self.writer_pid.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn reader_pid(&self) -> u32 {
+        self.reader_pid.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn messages_pushed(&self) -> u64 {
+        self.writer.messages_pushed.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_pushes(&self, messages: usize, bytes: usize) {
+        bump(&self.writer.messages_pushed, messages as u64);
+        bump(&self.writer.bytes_pushed, bytes as u64);
+    }
+
+    pub(crate) fn record_writer_segment(&self, epoch: u64) {
+        if epoch > 0 {
+            bump(&self.writer.rotations, 1);
+        }
+        self.writer.writer_segment.store(epoch, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_pop(&self, bytes: usize) {
+        bump(&self.reader.messages_popped, 1);
+        bump(&self.reader.bytes_popped, bytes as u64);
+    }
+
+    pub(crate) fn record_reader_segment(&self, epoch: u64) {
+        self.reader.reader_segment.store(epoch, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self, mapped_segments: usize, mapped_bytes: usize) -> Stats {
+        // popped counters go first, so that depth never comes out negative
+        let messages_popped = self.reader.messages_popped.load(Ordering::Relaxed);
+        let bytes_popped = self.reader.bytes_popped.load(Ordering::Relaxed);
+        let reader_segment = self.reader.reader_segment.load(Ordering::Relaxed);
+
+        let messages_pushed = self.writer.messages_pushed.load(Ordering::Relaxed);
+        let bytes_pushed = self.writer.bytes_pushed.load(Ordering::Relaxed);
+        let writer_segment = self.writer.writer_segment.load(Ordering::Relaxed);
+
+        Stats {
+            messages_pushed,
+            bytes_pushed,
+            messages_popped,
+            bytes_popped,
+            depth_messages: messages_pushed.saturating_sub(messages_popped),
+            depth_bytes: bytes_pushed.saturating_sub(bytes_popped),
+            rotations: self.writer.rotations.load(Ordering::Relaxed),
+            writer_segment,
+            reader_segment,
+            live_segments: writer_segment.saturating_sub(reader_segment) + 1,
+            mapped_segments,
+            mapped_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::offset_of;
+
+    #[test]
+    fn test_counters_on_their_own_cache_lines() {
+        let writer = offset_of!(ChannelHeader, writer);
+        let reader = offset_of!(ChannelHeader, reader);
+        assert_eq!(writer % 64, 0);
+        assert_eq!(reader % 64, 0);
+        assert!(reader - writer >= 64);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    pub messages_pushed: u64,
+    pub bytes_pushed: u64,
+    pub messages_popped: u64,
+    pub bytes_popped: u64,
+
+    // what's pushed but not popped yet, i.e. how far behind the reader is
+    pub depth_messages: u64,
+    pub depth_bytes: u64,
+
+    pub rotations: u64,
+    // epochs of the segments both sides are currently on
+    pub writer_segment: u64,
+    pub reader_segment: u64,
+    // segments from the reader's one to the writer's one
+    pub live_segments: u64,
+
+    // segments mapped by the process that took the snapshot
+    pub mapped_segments: usize,
+    pub mapped_bytes: usize,
+}
@@ -20,6 +20,27 @@ impl ConnectionType {
         }
     }
 
+    pub fn slab(prefix: &str) -> Self {
+        let id = format!("/{}-slab", prefix);
+        Self {
+            id: CString::new(id).unwrap(),
+        }
+    }
+
+    pub fn pod(prefix: &str) -> Self {
+        let id = format!("/{}-pod", prefix);
+        Self {
+            id: CString::new(id).unwrap(),
+        }
+    }
+
+    pub fn blob(generation: u64, sequence: u64, prefix: &str) -> Self {
+        let id = format!("/{}-blob-{}-{}", prefix, generation, sequence);
+        Self {
+            id: CString::new(id).unwrap(),
+        }
+    }
+
     pub fn exact(name: &[u8]) -> Self {
         Self {
             id: CString::new(name.to_vec()).unwrap(),
@@ -46,4 +67,8 @@ impl ConnectionType {
     pub fn id(&self) -> &CStr {
         self.id.as_c_str()
     }
+
+    pub fn name(&self) -> String {
+        self.id.to_string_lossy().into_owned()
+    }
 }
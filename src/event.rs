@@ -0,0 +1,19 @@
+// Events are only emitted with the `log` feature enabled, without it
+// nothing is ever printed. Every event is a stable name followed by
+// `key=value` fields, e.g. `writer.segment_created segment="/x-worker-0" size=1048 addr=0x7f..`
+macro_rules! event {
+    ($level:ident, $name:literal $(, $key:ident = $value:expr)* $(,)?) => {{
+        #[cfg(feature = "log")]
+        ::log::$level!(
+            target: "native_ipc_rust",
+            concat!($name $(, " ", stringify!($key), "={:?}")*)
+            $(, $value)*
+        );
+        #[cfg(not(feature = "log"))]
+        {
+            $(let _ = &$value;)*
+        }
+    }};
+}
+
+pub(crate) use event;
@@ -0,0 +1,215 @@
+// Every message is a frame in the data area of a worker segment:
+//
+//   length: u8 | kind: u8 | sequence: u64 | timestamp: u64 (if TIMESTAMPED) | payload: [u8; length]
+//
+// The sequence number counts messages from 0 for every writer generation,
+// it carries on across segments so that the reader can prove nothing is lost.
+// Messages that don't fit in a frame are split into fragments (see `Kind`),
+// all of them carrying the sequence number of the message.
+// Large ones can go in a segment of their own instead (see `blob`).
+// Which optional fields are present is decided by the writer per segment
+// and recorded in the `flags` of the segment header, so readers (and
+// observers) never have to be told how the writer is configured
+
+use std::sync::atomic::AtomicUsize;
+
+// CLOCK_MONOTONIC nanoseconds at push time, little endian
+pub(crate) const TIMESTAMPED: u64 = 1;
+// The cursors are the padded ones of the segment header, see `Layout`
+pub(crate) const PADDED: u64 = 2;
+// Frames are preceded by as much padding as it takes
+// for their payload to start on an 8 (or 64) byte boundary
+pub(crate) const ALIGNED_8: u64 = 4;
+pub(crate) const ALIGNED_64: u64 = 8;
+
+// A cursor only one side ever stores, alone on its cache line
+#[repr(C, align(64))]
+#[derive(Debug, Default)]
+pub(crate) struct CachePadded(pub(crate) AtomicUsize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Whole = 0,
+    // the payload starts with the length of the whole message, u64 little endian
+    First = 1,
+    Middle = 2,
+    Last = 3,
+    // the payload is a descriptor of a segment of its own, see `blob`
+    Blob = 4,
+}
+
+// Prefix of the payload of a `First` fragment
+pub(crate) const FRAGMENTED_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Frame<'a> {
+    pub(crate) kind: Kind,
+    pub(crate) sequence: u64,
+    pub(crate) timestamp: Option<u64>,
+    pub(crate) payload: &'a [u8],
+}
+
+// Everything in front of the payload
+pub(crate) fn header_len(flags: u64) -> usize {
+    let mut len = 1 + 1 + 8;
+    if flags & TIMESTAMPED != 0 {
+        len += 8;
+    }
+    len
+}
+
+pub(crate) fn alignment(flags: u64) -> usize {
+    if flags & ALIGNED_64 != 0 {
+        64
+    } else if flags & ALIGNED_8 != 0 {
+        8
+    } else {
+        1
+    }
+}
+
+// Where the header of the frame after one ending at `at` goes.
+// Cursors always point right past a payload, the padding
+// in front of the next frame is skipped by both sides alike
+pub(crate) fn next_frame(at: usize, flags: u64) -> usize {
+    payload_at(at, flags) - header_len(flags)
+}
+
+// Where its payload goes
+pub(crate) fn payload_at(at: usize, flags: u64) -> usize {
+    (at + header_len(flags)).next_multiple_of(alignment(flags))
+}
+
+pub(crate) fn encode_header(
+    buffer: &mut [u8],
+    flags: u64,
+    payload_len: usize,
+    kind: Kind,
+    sequence: u64,
+    timestamp: u64,
+) -> usize {
+    buffer[0] = payload_len as u8;
+    buffer[1] = kind as u8;
+    buffer[2..10].copy_from_slice(&sequence.to_le_bytes());
+    let mut at = 10;
+    if flags & TIMESTAMPED != 0 {
+        buffer[at..at + 8].copy_from_slice(&timestamp.to_le_bytes());
+        at += 8;
+    }
+    at
+}
+
+// Frame after `data[..at]` and the offset right past it.
+// Out of bounds (or otherwise broken) frames can only be seen when racing
+// with the writer resetting the segment, they are treated as the end of the queue
+pub(crate) fn decode(data: &[u8], at: usize, flags: u64) -> Option<(Frame<'_>, usize)> {
+    let at = next_frame(at, flags);
+    let length = *data.get(at)? as usize;
+    let kind = match *data.get(at + 1)? {
+        0 => Kind::Whole,
+        1 => Kind::First,
+        2 => Kind::Middle,
+        3 => Kind::Last,
+        4 => Kind::Blob,
+        _ => return None,
+    };
+    let sequence = u64::from_le_bytes(data.get(at + 2..at + 10)?.try_into().unwrap());
+    let mut payload_at = at + 10;
+
+    let mut timestamp = None;
+    if flags & TIMESTAMPED != 0 {
+        let bytes = data.get(payload_at..payload_at + 8)?;
+        timestamp = Some(u64::from_le_bytes(bytes.try_into().unwrap()));
+        payload_at += 8;
+    }
+
+    let payload = data.get(payload_at..payload_at + length)?;
+    let frame = Frame {
+        kind,
+        sequence,
+        timestamp,
+        payload,
+    };
+    Some((frame, payload_at + length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for flags in [0, TIMESTAMPED] {
+            let mut data = vec![0; 64];
+            let mut at = 0;
+            let frames = [(&b"abc"[..], Kind::Whole, 42), (&b""[..], Kind::Last, 43)];
+            for (i, (payload, kind, timestamp)) in frames.into_iter().enumerate() {
+                at += encode_header(
+                    &mut data[at..],
+                    flags,
+                    payload.len(),
+                    kind,
+                    i as u64,
+                    timestamp,
+                );
+                data[at..at + payload.len()].copy_from_slice(payload);
+                at += payload.len();
+            }
+            assert_eq!(at, 2 * header_len(flags) + 3);
+
+            let (first, next) = decode(&data, 0, flags).unwrap();
+            let (second, end) = decode(&data, next, flags).unwrap();
+            assert_eq!(end, at);
+            assert_eq!(first.payload, b"abc");
+            assert_eq!(second.payload, b"");
+            assert_eq!((first.sequence, second.sequence), (0, 1));
+            assert_eq!((first.kind, second.kind), (Kind::Whole, Kind::Last));
+            if flags & TIMESTAMPED != 0 {
+                assert_eq!((first.timestamp, second.timestamp), (Some(42), Some(43)));
+            } else {
+                assert_eq!((first.timestamp, second.timestamp), (None, None));
+            }
+        }
+    }
+
+    #[test]
+    fn test_aligned() {
+        assert_eq!(next_frame(3, 0), 3);
+        assert_eq!(payload_at(3, 0), 13);
+        assert_eq!(next_frame(0, ALIGNED_8), 6);
+        assert_eq!(payload_at(0, ALIGNED_8), 16);
+        assert_eq!(payload_at(6, ALIGNED_8), 16);
+        assert_eq!(payload_at(7, ALIGNED_8), 24);
+        assert_eq!(payload_at(0, ALIGNED_64 | TIMESTAMPED), 64);
+        assert_eq!(next_frame(64, ALIGNED_64 | TIMESTAMPED), 110);
+
+        let flags = ALIGNED_8 | PADDED;
+        let mut data = vec![0xff; 64];
+        let at = payload_at(3, flags);
+        encode_header(
+            &mut data[next_frame(3, flags)..],
+            flags,
+            2,
+            Kind::Whole,
+            7,
+            0,
+        );
+        data[at..at + 2].copy_from_slice(b"ab");
+
+        let (frame, next) = decode(&data, 3, flags).unwrap();
+        assert_eq!((frame.payload, frame.sequence), (&b"ab"[..], 7));
+        assert_eq!(next, 18);
+        // the padding in front isn't looked at
+        assert_eq!(decode(&data, 2, flags), decode(&data, 3, flags));
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert_eq!(decode(&[5, 1, 2], 0, 0), None);
+        assert_eq!(decode(&[1; 10], 0, 0), None);
+        assert_eq!(decode(&[0; 10], 0, TIMESTAMPED), None);
+        // unknown kind
+        assert_eq!(decode(&[0, 5, 0, 0, 0, 0, 0, 0, 0, 0], 0, 0), None);
+        assert_eq!(decode(&[], 0, 0), None);
+    }
+}
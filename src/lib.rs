@@ -1,15 +1,55 @@
 mod capi;
 
+mod event;
+
+mod frame;
+
+mod blob;
+
+mod channel;
+pub use channel::Stats;
+
+mod segment;
+pub use segment::{Advice, Prefault, SegmentOptions};
+
 mod connection_type;
 pub use connection_type::ConnectionType;
 
 mod writer;
 pub use writer::{
-    Writer, WriterConnectError, WriterConnection, WriterDisconnectError, WriterError,
+    Layout, ShutdownReport, WriteGuard, Writer, WriterConnectError, WriterConnection,
+    WriterDisconnectError, WriterError, WriterOptions,
 };
 
 mod reader;
-pub use reader::{Reader, ReaderConnectError, ReaderConnection};
+pub use reader::{
+    ChannelState, LatencySummary, Message, MessageRef, Observer, Position, QueueState, Reader,
+    ReaderConnectError, ReaderConnection, ReaderError, ReaderOptions, SegmentState, SequenceGap,
+    SequenceReport,
+};
+
+mod typed;
+#[cfg(feature = "serde")]
+pub use typed::BincodeCodec;
+pub use typed::{
+    fingerprint, Codec, CodecError, RawCodec, TypedError, TypedReader, TypedWriter, Utf8Codec,
+};
+
+mod slab;
+pub use slab::{Slab, SlabBuffer, SlabError, SlabHandle, SLAB_HANDLE_LEN};
+
+mod pod;
+pub use pod::{Pod, PodError, PodReader, PodRef, PodWriter};
+
+mod transport;
+pub use transport::{
+    InProcessReader, InProcessTransport, InProcessWriter, ShmTransport, Transport, TransportConfig,
+    TransportError, TransportReader, TransportWriter, UnixSocketReader, UnixSocketTransport,
+    UnixSocketWriter,
+};
+
+mod shm_dir;
+pub use shm_dir::{list_channels, purge_channel, shm_space, ChannelInfo, SegmentInfo, ShmSpace};
 
 #[cfg(test)]
 mod random_name;
@@ -0,0 +1,71 @@
+use std::{error::Error, fmt, io};
+
+use crate::{ReaderConnectError, WriterConnectError};
+
+#[derive(Debug)]
+pub enum PodError {
+    CreateError(WriterConnectError),
+    OpenError(ReaderConnectError),
+    // the reader hasn't made room yet
+    Full,
+    // the writer has been built for another `T`
+    TypeMismatch { expected: u64, found: u64 },
+    // another writer is still alive on the same prefix
+    InUse { segment: String, pid: u32 },
+}
+
+impl fmt::Display for PodError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CreateError(_) => f.write_str("failed to create the pod segment"),
+            Self::OpenError(_) => f.write_str("failed to open the pod segment"),
+            Self::Full => f.write_str("no free slot in the pod segment"),
+            Self::TypeMismatch { expected, found } => write!(
+                f,
+                "pod segment carries type {:016x}, expected {:016x}",
+                found, expected
+            ),
+            Self::InUse { segment, pid } => {
+                write!(f, "pod segment {:?} is in use by pid {}", segment, pid)
+            }
+        }
+    }
+}
+
+impl Error for PodError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::CreateError(err) => Some(err),
+            Self::OpenError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<PodError> for io::Error {
+    fn from(err: PodError) -> Self {
+        let kind = match &err {
+            PodError::CreateError(err) => err.io_error().kind(),
+            PodError::OpenError(err) => match err.io_error() {
+                Some(source) => source.kind(),
+                None => io::ErrorKind::WouldBlock,
+            },
+            PodError::Full => io::ErrorKind::WouldBlock,
+            PodError::TypeMismatch { .. } => io::ErrorKind::InvalidData,
+            PodError::InUse { .. } => io::ErrorKind::AddrInUse,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+impl From<WriterConnectError> for PodError {
+    fn from(err: WriterConnectError) -> Self {
+        Self::CreateError(err)
+    }
+}
+
+impl From<ReaderConnectError> for PodError {
+    fn from(err: ReaderConnectError) -> Self {
+        Self::OpenError(err)
+    }
+}
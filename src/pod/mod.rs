@@ -0,0 +1,507 @@
+mod error;
+pub use error::PodError;
+
+use std::{
+    any::type_name,
+    marker::PhantomData,
+    ops::Deref,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+};
+
+use libc::{
+    MAP_SHARED, O_CREAT, O_EXCL, O_RDONLY, O_RDWR, PROT_READ, PROT_WRITE, S_IRUSR, S_IWUSR,
+};
+
+use crate::{
+    capi::{close, fstat, mmap, munmap, process_exists, shm_open, shm_unlink},
+    event::event,
+    typed::fingerprint,
+    writer::allocate,
+    ConnectionType, ReaderConnectError, WriterConnectError,
+};
+
+/// Types that can be read straight out of shared memory.
+///
+/// # Safety
+///
+/// Whatever bytes the writer has left in a slot are read as a `T`, so every
+/// bit pattern has to be a valid one (no `bool`, `char`, enums, references
+/// or pointers in there), and the layout has to be the same on both sides,
+/// i.e. `#[repr(C)]` made of `Pod` fields
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! pod {
+    ($($t:ty),*) => {
+        $(unsafe impl Pod for $t {})*
+    };
+}
+
+pod!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+// A ring of `T` slots in a segment of its own, every one of them aligned
+// for `T`, so the reader can hand out references into it. Unlike worker
+// queues it's never rotated: the writer waits for the reader to make room.
+//
+// The header is followed by `capacity` slots, starting on a cache line.
+// `head` and `tail` count slots written and read so far, each of them
+// is only ever stored by one side
+#[repr(C)]
+struct Header {
+    type_fingerprint: u64,
+    capacity: u64,
+    writer_pid: AtomicU32,
+    initialized: AtomicBool,
+    head: Padded,
+    tail: Padded,
+}
+
+// Keeps the writer's and the reader's counters off each other's cache line
+#[repr(C, align(64))]
+struct Padded(AtomicU64);
+
+const CACHE_LINE: usize = 64;
+
+fn slots_offset<T>() -> usize {
+    // the segment itself is page aligned
+    std::mem::size_of::<Header>().next_multiple_of(std::mem::align_of::<T>().max(CACHE_LINE))
+}
+
+fn segment_size<T>(capacity: usize) -> usize {
+    slots_offset::<T>() + capacity * std::mem::size_of::<T>()
+}
+
+// Name, size and alignment of `T`, none of them are allowed to change
+fn type_fingerprint<T>() -> u64 {
+    fingerprint(&[
+        type_name::<T>(),
+        &std::mem::size_of::<T>().to_string(),
+        &std::mem::align_of::<T>().to_string(),
+    ])
+}
+
+#[derive(Debug)]
+struct Segment {
+    fd: i32,
+    addr: *mut std::ffi::c_void,
+    size: usize,
+    connection_type: ConnectionType,
+}
+
+impl Segment {
+    fn header(&self) -> &Header {
+        unsafe { &*self.addr.cast::<Header>() }
+    }
+
+    fn slot<T>(&self, index: u64) -> *mut T {
+        let capacity = self.header().capacity;
+        unsafe {
+            self.addr
+                .cast::<u8>()
+                .add(slots_offset::<T>())
+                .cast::<T>()
+                .add((index % capacity) as usize)
+        }
+    }
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        if !self.addr.is_null() {
+            let _ = munmap(self.addr, self.size);
+        }
+        let _ = close(self.fd);
+    }
+}
+
+// Pid of the writer that has set the segment up, if that one is still running
+fn live_writer(connection_type: &ConnectionType) -> Option<u32> {
+    let fd = shm_open(
+        connection_type.id(),
+        O_RDONLY,
+        (S_IRUSR | S_IWUSR) as std::ffi::c_uint,
+    )
+    .ok()?;
+    let mut segment = Segment {
+        fd,
+        addr: std::ptr::null_mut(),
+        size: std::mem::size_of::<Header>(),
+        connection_type: connection_type.clone(),
+    };
+    if (fstat(fd).ok()?.st_size as usize) < segment.size {
+        return None;
+    }
+    segment.addr = mmap(
+        std::ptr::null_mut(),
+        segment.size,
+        PROT_READ,
+        MAP_SHARED,
+        fd,
+        0,
+    )
+    .ok()?;
+
+    let header = segment.header();
+    if !header.initialized.load(Ordering::Acquire) {
+        return None;
+    }
+    let pid = header.writer_pid.load(Ordering::Relaxed);
+    process_exists(pid).then_some(pid)
+}
+
+pub struct PodWriter<T: Pod> {
+    segment: Segment,
+    head: u64,
+    pod: PhantomData<T>,
+}
+
+impl<T: Pod> PodWriter<T> {
+    pub fn new(prefix: &str, capacity: usize) -> Result<Self, PodError> {
+        let connection_type = ConnectionType::pod(prefix);
+        let size = segment_size::<T>(capacity.max(1));
+
+        // a reader of the segment would be left with the old one
+        if let Some(pid) = live_writer(&connection_type) {
+            return Err(PodError::InUse {
+                segment: connection_type.name(),
+                pid,
+            });
+        }
+        // same as worker segments, a stale one may still be mapped somewhere
+        let _ = shm_unlink(connection_type.id());
+
+        let fd = shm_open(
+            connection_type.id(),
+            O_RDWR | O_CREAT | O_EXCL,
+            (S_IRUSR | S_IWUSR) as std::ffi::c_uint,
+        )
+        .map_err(|source| WriterConnectError::ShmOpenError {
+            segment: connection_type.name(),
+            source,
+        })?;
+
+        let mut writer = Self {
+            segment: Segment {
+                fd,
+                addr: std::ptr::null_mut(),
+                size,
+                connection_type,
+            },
+            head: 0,
+            pod: PhantomData,
+        };
+        let segment = &mut writer.segment;
+
+        allocate(fd, size, &segment.connection_type)?;
+        segment.addr =
+            mmap(std::ptr::null_mut(), size, PROT_WRITE, MAP_SHARED, fd, 0).map_err(|source| {
+                WriterConnectError::MmapError {
+                    segment: segment.connection_type.name(),
+                    source,
+                }
+            })?;
+
+        let header = Header {
+            type_fingerprint: type_fingerprint::<T>(),
+            capacity: capacity.max(1) as u64,
+            writer_pid: AtomicU32::new(std::process::id()),
+            initialized: AtomicBool::new(false),
+            head: Padded(AtomicU64::new(0)),
+            tail: Padded(AtomicU64::new(0)),
+        };
+        unsafe { std::ptr::write(segment.addr.cast::<Header>(), header) };
+        segment.header().initialized.store(true, Ordering::Release);
+
+        event!(
+            debug,
+            "pod.created",
+            segment = segment.connection_type.name(),
+            type_name = type_name::<T>(),
+            capacity = capacity,
+            size = size,
+        );
+
+        Ok(writer)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.segment.header().capacity as usize
+    }
+
+    // Pushed but not popped yet
+    pub fn pending(&self) -> usize {
+        (self.head - self.segment.header().tail.0.load(Ordering::Acquire)) as usize
+    }
+
+    pub fn push(&mut self, value: &T) -> Result<(), PodError> {
+        if self.pending() == self.capacity() {
+            return Err(PodError::Full);
+        }
+        unsafe { std::ptr::write(self.segment.slot::<T>(self.head), *value) };
+        self.head += 1;
+        self.segment
+            .header()
+            .head
+            .0
+            .store(self.head, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl<T: Pod> Drop for PodWriter<T> {
+    fn drop(&mut self) {
+        // a reader keeps its mapping until it goes away
+        let _ = shm_unlink(self.segment.connection_type.id());
+    }
+}
+
+pub struct PodReader<T: Pod> {
+    segment: Segment,
+    pod: PhantomData<T>,
+}
+
+impl<T: Pod> PodReader<T> {
+    pub fn new(prefix: &str) -> Result<Self, PodError> {
+        let connection_type = ConnectionType::pod(prefix);
+
+        let fd = shm_open(
+            connection_type.id(),
+            O_RDWR,
+            (S_IRUSR | S_IWUSR) as std::ffi::c_uint,
+        )
+        .map_err(|source| ReaderConnectError::ShmOpenError {
+            segment: connection_type.name(),
+            source,
+        })?;
+
+        let mut reader = Self {
+            segment: Segment {
+                fd,
+                addr: std::ptr::null_mut(),
+                size: 0,
+                connection_type,
+            },
+            pod: PhantomData,
+        };
+        let segment = &mut reader.segment;
+
+        let uninitialized = || ReaderConnectError::Uninitialized {
+            segment: segment.connection_type.name(),
+        };
+        let stat = fstat(fd).map_err(|source| ReaderConnectError::FstatError {
+            segment: segment.connection_type.name(),
+            source,
+        })?;
+        let size = stat.st_size as usize;
+        if size < std::mem::size_of::<Header>() {
+            return Err(uninitialized().into());
+        }
+
+        let addr =
+            mmap(std::ptr::null_mut(), size, PROT_WRITE, MAP_SHARED, fd, 0).map_err(|source| {
+                ReaderConnectError::MmapError {
+                    segment: segment.connection_type.name(),
+                    source,
+                }
+            })?;
+        segment.addr = addr;
+        segment.size = size;
+
+        let header = segment.header();
+        if !header.initialized.load(Ordering::Acquire) {
+            return Err(uninitialized().into());
+        }
+        let expected = type_fingerprint::<T>();
+        if header.type_fingerprint != expected {
+            return Err(PodError::TypeMismatch {
+                expected,
+                found: header.type_fingerprint,
+            });
+        }
+        if segment_size::<T>(header.capacity as usize) != size {
+            return Err(uninitialized().into());
+        }
+
+        Ok(reader)
+    }
+
+    // The value is right in the segment, its slot is only handed
+    // back to the writer when the reference is dropped
+    pub fn pop_ref(&mut self) -> Option<PodRef<'_, T>> {
+        let header = self.segment.header();
+        let tail = header.tail.0.load(Ordering::Relaxed);
+        if tail == header.head.0.load(Ordering::Acquire) {
+            return None;
+        }
+        Some(PodRef {
+            value: unsafe { &*self.segment.slot::<T>(tail) },
+            tail: &header.tail.0,
+            next: tail + 1,
+        })
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.pop_ref().map(|value| *value)
+    }
+}
+
+pub struct PodRef<'a, T> {
+    value: &'a T,
+    tail: &'a AtomicU64,
+    next: u64,
+}
+
+impl<T> Deref for PodRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for PodRef<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+impl<T> Drop for PodRef<'_, T> {
+    fn drop(&mut self) {
+        self.tail.store(self.next, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Tick {
+        price: f64,
+        size: u32,
+        venue: [u8; 4],
+    }
+
+    unsafe impl Pod for Tick {}
+
+    #[repr(C, align(128))]
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Aligned(u8);
+
+    unsafe impl Pod for Aligned {}
+
+    #[test]
+    fn test_push_and_pop() {
+        let prefix = crate::random_name();
+
+        let mut writer = PodWriter::<Tick>::new(&prefix, 2).unwrap();
+        let mut reader = PodReader::<Tick>::new(&prefix).unwrap();
+        assert!(reader.pop_ref().is_none());
+
+        let tick = |i| Tick {
+            price: i as f64 / 2.0,
+            size: i,
+            venue: *b"XNAS",
+        };
+        writer.push(&tick(1)).unwrap();
+        writer.push(&tick(2)).unwrap();
+        assert!(matches!(writer.push(&tick(3)), Err(PodError::Full)));
+
+        {
+            let first = reader.pop_ref().unwrap();
+            assert_eq!(*first, tick(1));
+            // not handed back yet
+            assert_eq!(writer.pending(), 2);
+            let address = &*first as *const Tick as usize;
+            assert_eq!(address % std::mem::align_of::<Tick>(), 0);
+        }
+        assert_eq!(writer.pending(), 1);
+
+        // wraps around
+        writer.push(&tick(3)).unwrap();
+        assert_eq!(reader.pop(), Some(tick(2)));
+        assert_eq!(reader.pop(), Some(tick(3)));
+        assert_eq!(reader.pop(), None);
+        assert_eq!(writer.pending(), 0);
+    }
+
+    #[test]
+    fn test_alignment() {
+        let prefix = crate::random_name();
+
+        let mut writer = PodWriter::<Aligned>::new(&prefix, 4).unwrap();
+        let mut reader = PodReader::<Aligned>::new(&prefix).unwrap();
+
+        for i in 0..10 {
+            writer.push(&Aligned(i)).unwrap();
+            let value = reader.pop_ref().unwrap();
+            assert_eq!(*value, Aligned(i));
+            assert_eq!(&*value as *const Aligned as usize % 128, 0);
+        }
+    }
+
+    #[test]
+    fn test_type_mismatch() {
+        let prefix = crate::random_name();
+
+        let _writer = PodWriter::<Tick>::new(&prefix, 2).unwrap();
+        let err = PodReader::<[u8; 24]>::new(&prefix).map(|_| ()).unwrap_err();
+        assert!(matches!(err, PodError::TypeMismatch { .. }));
+        assert_eq!(
+            std::io::Error::from(err).kind(),
+            std::io::ErrorKind::InvalidData
+        );
+
+        let err = PodReader::<Tick>::new(&crate::random_name())
+            .map(|_| ())
+            .unwrap_err();
+        assert!(matches!(err, PodError::OpenError(_)));
+    }
+
+    #[test]
+    fn test_in_use() {
+        let prefix = crate::random_name();
+
+        let mut writer = PodWriter::<u64>::new(&prefix, 2).unwrap();
+        let mut reader = PodReader::<u64>::new(&prefix).unwrap();
+        let err = PodWriter::<u64>::new(&prefix, 2).map(|_| ()).unwrap_err();
+        assert!(matches!(&err, PodError::InUse { pid, .. } if *pid == std::process::id()));
+        assert_eq!(
+            std::io::Error::from(err).kind(),
+            std::io::ErrorKind::AddrInUse
+        );
+
+        // still the same segment
+        writer.push(&1).unwrap();
+        assert_eq!(reader.pop(), Some(1));
+
+        drop(writer);
+        PodWriter::<u64>::new(&prefix, 2).unwrap();
+    }
+
+    #[test]
+    fn test_threads() {
+        let prefix = crate::random_name();
+
+        let mut writer = PodWriter::<u64>::new(&prefix, 16).unwrap();
+
+        let reader_thread = std::thread::spawn(move || {
+            let mut reader = PodReader::<u64>::new(&prefix).unwrap();
+            let mut expected = 0;
+            while expected < 100_000 {
+                if let Some(value) = reader.pop() {
+                    assert_eq!(value, expected);
+                    expected += 1;
+                }
+            }
+        });
+        for i in 0..100_000u64 {
+            while let Err(PodError::Full) = writer.push(&i) {
+                std::hint::spin_loop();
+            }
+        }
+        reader_thread.join().unwrap();
+    }
+}
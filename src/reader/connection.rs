@@ -1,44 +1,120 @@
-use libc::{MAP_SHARED, O_RDWR, PROT_WRITE, S_IRUSR, S_IWUSR};
+use libc::{MAP_SHARED, O_RDONLY, O_RDWR, PROT_READ, PROT_WRITE, S_IRUSR, S_IWUSR};
 
 use crate::{
-    capi::{mmap, shm_open},
+    capi::{close, fstat, mmap, munmap, shm_open},
+    event::event,
     reader::{queue::Queue, ReaderConnectError},
+    segment::{Failure, SegmentOptions},
     ConnectionType,
 };
 
 #[derive(Debug)]
 pub struct ReaderConnection<const QUEUE_SIZE: usize> {
+    fd: i32,
     addr: *mut std::ffi::c_void,
+    size: usize,
+    // mapped PROT_READ, see `read_only`
+    read_only: bool,
     connection_type: ConnectionType,
 }
 
 impl<const QUEUE_SIZE: usize> ReaderConnection<QUEUE_SIZE> {
+    pub(crate) const SIZE: usize = Queue::<QUEUE_SIZE>::SIZE;
+
     pub fn new(connection_type: ConnectionType) -> Result<Self, ReaderConnectError> {
+        Self::with_options(connection_type, &SegmentOptions::default())
+    }
+
+    pub fn with_options(
+        connection_type: ConnectionType,
+        options: &SegmentOptions,
+    ) -> Result<Self, ReaderConnectError> {
+        Self::open(connection_type, O_RDWR, PROT_WRITE, options)
+    }
+
+    // Any attempt to write through it is a segfault,
+    // so whoever holds it can't move the cursors by accident.
+    // The whole segment is mapped, whatever size the writer has given it,
+    // so that it can be inspected without knowing QUEUE_SIZE (see `data`)
+    pub fn read_only(connection_type: ConnectionType) -> Result<Self, ReaderConnectError> {
+        Self::open(
+            connection_type,
+            O_RDONLY,
+            PROT_READ,
+            &SegmentOptions::default(),
+        )
+    }
+
+    fn open(
+        connection_type: ConnectionType,
+        oflag: std::ffi::c_int,
+        protection: std::ffi::c_int,
+        options: &SegmentOptions,
+    ) -> Result<Self, ReaderConnectError> {
         let fd = shm_open(
             connection_type.id(),
-            O_RDWR,
+            oflag,
             (S_IRUSR | S_IWUSR) as std::ffi::c_uint,
         )
-        .map_err(ReaderConnectError::ShmOpenError)?;
+        .map_err(|source| ReaderConnectError::ShmOpenError {
+            segment: connection_type.name(),
+            source,
+        })?;
 
-        let addr = mmap(
-            std::ptr::null_mut(),
-            QUEUE_SIZE,
-            PROT_WRITE,
-            MAP_SHARED,
-            fd,
-            0,
-        )
-        .map_err(ReaderConnectError::MmapError)?;
+        // the writer creates the segment and sizes it in two steps,
+        // mapping it in between would SIGBUS on the first access
+        let stat = fstat(fd).map_err(|source| {
+            let _ = close(fd);
+            ReaderConnectError::FstatError {
+                segment: connection_type.name(),
+                source,
+            }
+        })?;
+        if stat.st_size < Queue::<QUEUE_SIZE>::SIZE as i64 {
+            let _ = close(fd);
+            return Err(ReaderConnectError::Uninitialized {
+                segment: connection_type.name(),
+            });
+        }
+        let size = if oflag == O_RDONLY {
+            stat.st_size as usize
+        } else {
+            Queue::<QUEUE_SIZE>::SIZE
+        };
 
-        println!("reader: addr = {:?}", addr);
+        let flags = MAP_SHARED | options.mmap_flags();
+        let addr =
+            mmap(std::ptr::null_mut(), size, protection, flags, fd, 0).map_err(|source| {
+                let _ = close(fd);
+                ReaderConnectError::MmapError {
+                    segment: connection_type.name(),
+                    source,
+                }
+            })?;
 
+        // released on drop if anything below fails
         let conn = Self {
+            fd,
             addr,
+            size,
+            read_only: oflag == O_RDONLY,
             connection_type,
         };
+        options.apply(addr, size, false).map_err(|failure| {
+            let segment = conn.name();
+            match failure {
+                Failure::Madvise(source) => ReaderConnectError::MadviseError { segment, source },
+                Failure::Mlock(source) => ReaderConnectError::MlockError { segment, source },
+            }
+        })?;
 
-        println!("[Reader] connected to {:?}", conn.id());
+        event!(
+            debug,
+            "reader.segment_connected",
+            segment = conn.id(),
+            size = size,
+            addr = addr,
+        );
 
         Ok(conn)
     }
@@ -47,14 +123,61 @@ impl<const QUEUE_SIZE: usize> ReaderConnection<QUEUE_SIZE> {
         self.connection_type.id()
     }
 
+    // the writer unlinks its segments when it goes away
+    // and a restarted writer re-creates them from scratch
+    pub(crate) fn is_unlinked(&self) -> Result<bool, ReaderConnectError> {
+        let stat = fstat(self.fd).map_err(|source| ReaderConnectError::FstatError {
+            segment: self.connection_type.name(),
+            source,
+        })?;
+        Ok(stat.st_nlink == 0)
+    }
+
     pub(crate) fn queue(&self) -> &'static mut Queue<QUEUE_SIZE> {
+        debug_assert!(
+            !self.read_only,
+            "{} is mapped read-only, use queue_ref",
+            self.name()
+        );
         Queue::from_ptr(self.addr)
     }
+
+    pub(crate) fn queue_ref(&self) -> &Queue<QUEUE_SIZE> {
+        Queue::from_ptr_ref(self.addr)
+    }
+
+    pub(crate) fn name(&self) -> String {
+        self.connection_type.name()
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    // Data area of the segment as it's actually mapped,
+    // may be larger than QUEUE_SIZE for read-only connections
+    pub(crate) fn data(&self) -> &[u8] {
+        let offset = Queue::<QUEUE_SIZE>::DATA_OFFSET;
+        unsafe {
+            std::slice::from_raw_parts(self.addr.cast::<u8>().add(offset), self.size - offset)
+        }
+    }
+}
+
+impl<const N: usize> Drop for ReaderConnection<N> {
+    fn drop(&mut self) {
+        // the segment is owned by the writer, so it's only released here
+        let _ = munmap(self.addr, self.size);
+        let _ = close(self.fd);
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{ConnectionType, ReaderConnection, WriterConnection};
+    use crate::{
+        Advice, ConnectionType, Prefault, ReaderConnectError, ReaderConnection, SegmentOptions,
+        WriterConnection,
+    };
 
     #[test]
     fn test_success() {
@@ -70,15 +193,137 @@ mod tests {
         assert_eq!(unsafe { read_ptr.read() }, 42);
     }
 
+    #[test]
+    fn test_segment_options() {
+        let connection_type = ConnectionType::random();
+        let writer = WriterConnection::<100_000>::new(connection_type.clone()).unwrap();
+        unsafe { writer.addr.cast::<u8>().add(50_000).write(42) };
+
+        for prefault in [Prefault::Populate, Prefault::Touch] {
+            let options = SegmentOptions {
+                prefault,
+                lock: false,
+                advice: Some(Advice::WillNeed),
+            };
+            let reader =
+                ReaderConnection::<100_000>::with_options(connection_type.clone(), &options)
+                    .unwrap();
+            // only read
+            assert_eq!(
+                reader.data()[50_000 - super::Queue::<100_000>::DATA_OFFSET],
+                42
+            );
+        }
+
+        // well below RLIMIT_MEMLOCK, unless it's been lowered further
+        let connection_type = ConnectionType::random();
+        let _writer = WriterConnection::<1_000>::new(connection_type.clone()).unwrap();
+        let options = SegmentOptions {
+            prefault: Prefault::Touch,
+            lock: true,
+            advice: None,
+        };
+        if let Err(err) = ReaderConnection::<1_000>::with_options(connection_type.clone(), &options)
+        {
+            assert!(crate::segment::mlock_refused(&err), "{:?}", err);
+        }
+
+        let err = ReaderConnectError::MlockError {
+            segment: connection_type.name(),
+            source: std::io::Error::from_raw_os_error(libc::ENOMEM),
+        };
+        assert!(crate::segment::mlock_refused(&err));
+        assert_eq!(
+            err.to_string(),
+            format!("mlock failed for segment {:?}", connection_type.name())
+        );
+        assert_eq!(
+            std::io::Error::from(err).kind(),
+            std::io::ErrorKind::OutOfMemory
+        );
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "is mapped read-only")]
+    fn test_read_only_queue() {
+        let connection_type = ConnectionType::random();
+        let _writer = WriterConnection::<10>::new(connection_type.clone()).unwrap();
+        let reader = ReaderConnection::<10>::read_only(connection_type).unwrap();
+
+        assert!(!reader.queue_ref().is_done_writing());
+        reader.queue();
+    }
+
     #[test]
     fn test_reader_without_writer() {
         let connection_type = ConnectionType::random();
 
-        let err = ReaderConnection::<10>::new(connection_type).unwrap_err();
+        let err = ReaderConnection::<10>::new(connection_type.clone()).unwrap_err();
 
         assert_eq!(
-            format!("{:?}", err),
-            "ShmOpenError(\"No such file or directory\")"
+            err.to_string(),
+            format!("shm_open failed for segment {:?}", connection_type.name())
+        );
+        assert_eq!(err.operation(), "shm_open");
+        assert_eq!(err.segment(), connection_type.name());
+        assert_eq!(
+            err.io_error().map(std::io::Error::kind),
+            Some(std::io::ErrorKind::NotFound)
+        );
+
+        let err = std::io::Error::from(err);
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    // Descriptors of this process open on the segment
+    fn open_fds(connection_type: &ConnectionType) -> usize {
+        let path = std::path::PathBuf::from(format!("/dev/shm{}", connection_type.name()));
+        std::fs::read_dir("/proc/self/fd")
+            .unwrap()
+            .filter_map(|entry| std::fs::read_link(entry.ok()?.path()).ok())
+            .filter(|target| *target == path)
+            .count()
+    }
+
+    #[test]
+    fn test_failed_mmap_closes_fd() {
+        let connection_type = ConnectionType::random();
+        let _writer = WriterConnection::<10>::new(connection_type.clone()).unwrap();
+        assert_eq!(open_fds(&connection_type), 1);
+
+        // can't be mapped writable through a read-only descriptor
+        let err = ReaderConnection::<10>::open(
+            connection_type.clone(),
+            libc::O_RDONLY,
+            libc::PROT_WRITE,
+            &SegmentOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.operation(), "mmap");
+        assert_eq!(open_fds(&connection_type), 1);
+    }
+
+    #[test]
+    fn test_reader_before_ftruncate() {
+        let connection_type = ConnectionType::random();
+        let fd = crate::capi::shm_open(
+            connection_type.id(),
+            libc::O_RDWR | libc::O_CREAT,
+            (libc::S_IRUSR | libc::S_IWUSR) as std::ffi::c_uint,
         )
+        .unwrap();
+
+        let err = ReaderConnection::<10>::new(connection_type.clone()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "segment {:?} is not initialized yet",
+                connection_type.name()
+            )
+        );
+
+        crate::capi::close(fd).unwrap();
+        crate::capi::shm_unlink(connection_type.id()).unwrap();
     }
 }
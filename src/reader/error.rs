@@ -1,28 +1,178 @@
-use crate::capi::strerror;
+use std::{error::Error, fmt, io};
 
-#[derive(PartialEq)]
+#[derive(Debug)]
 pub enum ReaderConnectError {
-    ShmOpenError(Option<i32>),
-    MmapError(Option<i32>),
+    ShmOpenError { segment: String, source: io::Error },
+    MmapError { segment: String, source: io::Error },
+    FstatError { segment: String, source: io::Error },
+    MadviseError { segment: String, source: io::Error },
+    MlockError { segment: String, source: io::Error },
+    Uninitialized { segment: String },
 }
 
-impl std::fmt::Debug for ReaderConnectError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let (name, code) = match self {
-            Self::ShmOpenError(code) => ("ShmOpenError", *code),
-            Self::MmapError(code) => ("MmapError", *code),
-        };
+impl ReaderConnectError {
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::ShmOpenError { .. } => "shm_open",
+            Self::MmapError { .. } => "mmap",
+            Self::FstatError { .. } | Self::Uninitialized { .. } => "fstat",
+            Self::MadviseError { .. } => "madvise",
+            Self::MlockError { .. } => "mlock",
+        }
+    }
+
+    pub fn segment(&self) -> &str {
+        match self {
+            Self::ShmOpenError { segment, .. }
+            | Self::MmapError { segment, .. }
+            | Self::FstatError { segment, .. }
+            | Self::MadviseError { segment, .. }
+            | Self::MlockError { segment, .. }
+            | Self::Uninitialized { segment } => segment,
+        }
+    }
+
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            Self::ShmOpenError { source, .. }
+            | Self::MmapError { source, .. }
+            | Self::FstatError { source, .. }
+            | Self::MadviseError { source, .. }
+            | Self::MlockError { source, .. } => Some(source),
+            Self::Uninitialized { .. } => None,
+        }
+    }
+}
 
-        f.debug_tuple(name)
-            .field(&code.map(strerror).unwrap_or("Unspecified Error"))
-            .finish()
+impl fmt::Display for ReaderConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uninitialized { segment } => {
+                write!(f, "segment {:?} is not initialized yet", segment)
+            }
+            _ => write!(
+                f,
+                "{} failed for segment {:?}",
+                self.operation(),
+                self.segment()
+            ),
+        }
+    }
+}
+
+impl Error for ReaderConnectError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.io_error().map(|err| err as &(dyn Error + 'static))
+    }
+}
+
+impl From<ReaderConnectError> for io::Error {
+    fn from(err: ReaderConnectError) -> Self {
+        let kind = match err.io_error() {
+            Some(source) => source.kind(),
+            None => io::ErrorKind::WouldBlock,
+        };
+        io::Error::new(kind, err)
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Debug)]
 pub enum ReaderError {
     ReaderConnectError(ReaderConnectError),
     FailedToGetNextQueue,
+    Timeout,
+    QueueEpochMismatch {
+        expected: u64,
+        found: u64,
+    },
+    QueueGenerationMismatch {
+        expected: u64,
+        found: u64,
+    },
+    WriterRestarted {
+        previous_generation: u64,
+        generation: u64,
+    },
+    ShmDirError(io::Error),
+    MessageTooLarge {
+        sequence: u64,
+        len: usize,
+        max: usize,
+    },
+    ChecksumMismatch {
+        sequence: u64,
+        segment: String,
+    },
+}
+
+impl fmt::Display for ReaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReaderConnectError(_) => f.write_str("failed to connect to a segment"),
+            Self::FailedToGetNextQueue => f.write_str("no queue has been announced yet"),
+            Self::Timeout => f.write_str("timed out waiting for the writer"),
+            Self::QueueEpochMismatch { expected, found } => write!(
+                f,
+                "announced queue epoch {} doesn't match segment epoch {}",
+                expected, found
+            ),
+            Self::QueueGenerationMismatch { expected, found } => write!(
+                f,
+                "queue segment belongs to writer generation {}, expected {}",
+                found, expected
+            ),
+            Self::WriterRestarted {
+                previous_generation,
+                generation,
+            } => write!(
+                f,
+                "writer restarted (generation {} -> {}), messages may have been lost",
+                previous_generation, generation
+            ),
+            Self::ShmDirError(_) => f.write_str("failed to list segments in /dev/shm"),
+            Self::MessageTooLarge { sequence, len, max } => write!(
+                f,
+                "message {} of {} bytes is larger than {} bytes, it's been dropped",
+                sequence, len, max
+            ),
+            Self::ChecksumMismatch { sequence, segment } => write!(
+                f,
+                "payload of message {} in segment {:?} doesn't match its checksum",
+                sequence, segment
+            ),
+        }
+    }
+}
+
+impl Error for ReaderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::ReaderConnectError(err) => Some(err),
+            Self::ShmDirError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ReaderError> for io::Error {
+    fn from(err: ReaderError) -> Self {
+        let kind = match &err {
+            ReaderError::ReaderConnectError(err) => match err.io_error() {
+                Some(source) => source.kind(),
+                None => io::ErrorKind::WouldBlock,
+            },
+            ReaderError::FailedToGetNextQueue => io::ErrorKind::WouldBlock,
+            ReaderError::Timeout => io::ErrorKind::TimedOut,
+            ReaderError::QueueEpochMismatch { .. }
+            | ReaderError::QueueGenerationMismatch { .. } => io::ErrorKind::InvalidData,
+            ReaderError::WriterRestarted { .. } => io::ErrorKind::ConnectionReset,
+            ReaderError::ShmDirError(err) => err.kind(),
+            ReaderError::MessageTooLarge { .. } | ReaderError::ChecksumMismatch { .. } => {
+                io::ErrorKind::InvalidData
+            }
+        };
+        io::Error::new(kind, err)
+    }
 }
 
 impl From<ReaderConnectError> for ReaderError {
@@ -30,3 +180,17 @@ impl From<ReaderConnectError> for ReaderError {
         Self::ReaderConnectError(err)
     }
 }
+
+impl ReaderError {
+    // errors that go away on their own once the writer finishes start-up
+    pub(crate) fn is_transient(&self) -> bool {
+        match self {
+            Self::ReaderConnectError(err @ ReaderConnectError::ShmOpenError { .. }) => {
+                err.io_error().map(io::Error::kind) == Some(io::ErrorKind::NotFound)
+            }
+            Self::ReaderConnectError(ReaderConnectError::Uninitialized { .. }) => true,
+            Self::FailedToGetNextQueue => true,
+            _ => false,
+        }
+    }
+}
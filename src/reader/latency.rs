@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+// Log-linear buckets: values below 32ns get a bucket each, above that
+// every power of two is split into 32 buckets, so whatever is reported
+// is within ~3% of the actual value while the whole u64 range
+// fits in a fixed array that's never reallocated on the hot path
+const SUB_BUCKET_BITS: u32 = 5;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+const BUCKETS: usize = (64 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKETS;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub p50: Duration,
+    pub p99: Duration,
+    pub p999: Duration,
+    pub max: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct LatencyHistogram {
+    buckets: Box<[u64; BUCKETS]>,
+    count: u64,
+    max: u64,
+}
+
+fn bucket(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS as u64 {
+        return nanos as usize;
+    }
+    let shift = 63 - nanos.leading_zeros() - SUB_BUCKET_BITS;
+    let sub_bucket = (nanos >> shift) as usize - SUB_BUCKETS;
+    (shift as usize + 1) * SUB_BUCKETS + sub_bucket
+}
+
+// Largest value that falls into the bucket
+fn bucket_high(bucket: usize) -> u64 {
+    if bucket < SUB_BUCKETS {
+        return bucket as u64;
+    }
+    let shift = bucket / SUB_BUCKETS - 1;
+    let sub_bucket = (bucket % SUB_BUCKETS + SUB_BUCKETS) as u128;
+    // the last bucket ends at u64::MAX + 1
+    (((sub_bucket + 1) << shift) - 1).min(u64::MAX as u128) as u64
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: Box::new([0; BUCKETS]),
+            count: 0,
+            max: 0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub(crate) fn record(&mut self, nanos: u64) {
+        self.buckets[bucket(nanos)] += 1;
+        self.count += 1;
+        self.max = self.max.max(nanos);
+    }
+
+    fn percentile(&self, quantile: f64) -> u64 {
+        let rank = ((quantile * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return bucket_high(i).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    pub(crate) fn summary(&self) -> LatencySummary {
+        if self.count == 0 {
+            return LatencySummary::default();
+        }
+        LatencySummary {
+            count: self.count,
+            p50: Duration::from_nanos(self.percentile(0.5)),
+            p99: Duration::from_nanos(self.percentile(0.99)),
+            p999: Duration::from_nanos(self.percentile(0.999)),
+            max: Duration::from_nanos(self.max),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets() {
+        for nanos in [0, 1, 31, 32, 33, 63, 64, 1_000, 123_456_789, u64::MAX] {
+            let i = bucket(nanos);
+            assert!(i < BUCKETS);
+            assert!(nanos <= bucket_high(i));
+            assert!(i == 0 || nanos > bucket_high(i - 1));
+        }
+        // ~1ms is reported with a 16us resolution
+        let i = bucket(1_000_000);
+        assert_eq!(bucket_high(i) - bucket_high(i - 1), 1 << 14);
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.summary(), LatencySummary::default());
+
+        for nanos in 1..=1_000 {
+            histogram.record(nanos * 1_000);
+        }
+        let summary = histogram.summary();
+        assert_eq!(summary.count, 1_000);
+        assert_eq!(summary.max, Duration::from_micros(1_000));
+
+        let close_to = |actual: Duration, expected: u64| {
+            let expected = Duration::from_micros(expected);
+            actual >= expected && actual <= expected + expected / 32
+        };
+        assert!(close_to(summary.p50, 500));
+        assert!(close_to(summary.p99, 990));
+        assert!(close_to(summary.p999, 999));
+    }
+}
@@ -0,0 +1,119 @@
+use std::ops::Deref;
+
+use crate::{
+    blob::Blob,
+    frame::Frame,
+    reader::{queue::Queue, Tally},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub payload: Vec<u8>,
+    // counts messages from 0 for every writer generation
+    pub sequence: u64,
+    // CLOCK_MONOTONIC nanoseconds at push time,
+    // only if the writer is configured with `WriterOptions::timestamps`
+    pub timestamp: Option<u64>,
+}
+
+impl Message {
+    pub(crate) fn from_frame(frame: &Frame<'_>) -> Self {
+        Self {
+            payload: frame.payload.to_vec(),
+            sequence: frame.sequence,
+            timestamp: frame.timestamp,
+        }
+    }
+}
+
+// A message that's still in shared memory. The writer can't reuse
+// the segment until it's dropped, which is when the message is popped
+// (and counted in `Reader::stats` and `Reader::sequence_report`).
+// Fragmented messages are borrowed from the reader's reassembly buffer,
+// large ones are mapped from a segment of their own, which goes away with it
+pub struct MessageRef<'a, const QUEUE_SIZE: usize> {
+    queue: &'a Queue<QUEUE_SIZE>,
+    next: usize,
+    payload: &'a [u8],
+    blob: Option<Blob>,
+    sequence: u64,
+    timestamp: Option<u64>,
+    // taken on drop
+    tally: Option<Tally<'a>>,
+}
+
+impl<'a, const QUEUE_SIZE: usize> MessageRef<'a, QUEUE_SIZE> {
+    pub(crate) fn new(
+        queue: &'a Queue<QUEUE_SIZE>,
+        next: usize,
+        payload: &'a [u8],
+        sequence: u64,
+        timestamp: Option<u64>,
+        tally: Tally<'a>,
+    ) -> Self {
+        Self {
+            queue,
+            next,
+            payload,
+            blob: None,
+            sequence,
+            timestamp,
+            tally: Some(tally),
+        }
+    }
+
+    pub(crate) fn with_blob(mut self, blob: Blob) -> Self {
+        self.blob = Some(blob);
+        self
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        match &self.blob {
+            Some(blob) => blob.data(),
+            None => self.payload,
+        }
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    pub fn to_message(&self) -> Message {
+        Message {
+            payload: self.payload().to_vec(),
+            sequence: self.sequence,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+impl<const QUEUE_SIZE: usize> Deref for MessageRef<'_, QUEUE_SIZE> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.payload()
+    }
+}
+
+impl<const QUEUE_SIZE: usize> std::fmt::Debug for MessageRef<'_, QUEUE_SIZE> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MessageRef")
+            .field("payload", &self.payload())
+            .field("sequence", &self.sequence)
+            .field("timestamp", &self.timestamp)
+            .finish()
+    }
+}
+
+impl<const QUEUE_SIZE: usize> Drop for MessageRef<'_, QUEUE_SIZE> {
+    fn drop(&mut self) {
+        self.queue.advance(self.next);
+        if let Some(tally) = self.tally.take() {
+            tally.record(self.sequence, self.timestamp, self.payload().len());
+        }
+    }
+}
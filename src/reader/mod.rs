@@ -4,59 +4,571 @@ pub use connection::ReaderConnection;
 mod error;
 pub use error::{ReaderConnectError, ReaderError};
 
+mod latency;
+use latency::LatencyHistogram;
+pub use latency::LatencySummary;
+
+mod message;
+pub use message::{Message, MessageRef};
+
+mod sequence;
+use sequence::{Check, SequenceTracker};
+pub use sequence::{SequenceGap, SequenceReport};
+
+mod options;
+pub use options::ReaderOptions;
+
+mod reassembly;
+use reassembly::{Reassembly, Step};
+
+mod observer;
+pub use observer::{ChannelState, Observer, Position, QueueState, SegmentState};
+
 mod queue;
 
-use crate::ConnectionType;
+mod watcher;
+use watcher::{ShmWatcher, RECHECK_INTERVAL};
+
+use crate::{
+    blob::{self, Blob, Descriptor},
+    capi::monotonic_nanos,
+    channel::ChannelHeader,
+    event::event,
+    frame::Kind,
+    ConnectionType, SegmentOptions, Stats,
+};
+use std::time::{Duration, Instant};
 
 pub struct Reader<const QUEUE_SIZE: usize> {
     root_connection: ReaderConnection<1_000>,
     current_connection: ReaderConnection<QUEUE_SIZE>,
+    generation: u64,
+    prefix: String,
+    options: ReaderOptions,
+    latency: LatencyHistogram,
+    sequence: SequenceTracker,
+    reassembly: Reassembly,
+    // a restart is only checked for this often, see `ready`
+    next_restart_check: Instant,
+    // the root segment has been unlinked and there's no new writer yet
+    writer_gone: bool,
 }
 
 impl<const QUEUE_SIZE: usize> Reader<QUEUE_SIZE> {
     pub fn new(prefix: &str) -> Result<Self, ReaderError> {
+        Self::with_options(prefix, ReaderOptions::default())
+    }
+
+    pub fn with_options(prefix: &str, options: ReaderOptions) -> Result<Self, ReaderError> {
         let mut root_connection = ReaderConnection::new(ConnectionType::root(prefix))?;
-        let current_connection = Self::fetch_new_queue_connection(&mut root_connection)?;
-        Ok(Self {
+        let generation = root_connection.queue().generation;
+        let current_connection =
+            Self::fetch_new_queue_connection(&mut root_connection, generation, &options.segments)?;
+        Ok(Self::attached(
+            prefix,
+            options,
             root_connection,
             current_connection,
-        })
+        ))
+    }
+
+    pub fn connect_wait(prefix: &str, timeout: Duration) -> Result<Self, ReaderError> {
+        Self::connect_wait_with_options(prefix, timeout, ReaderOptions::default())
+    }
+
+    pub fn connect_wait_with_options(
+        prefix: &str,
+        timeout: Duration,
+        options: ReaderOptions,
+    ) -> Result<Self, ReaderError> {
+        let deadline = Instant::now() + timeout;
+        let watcher = ShmWatcher::new();
+
+        let mut root_connection = loop {
+            match ReaderConnection::new(ConnectionType::root(prefix)) {
+                Ok(connection) => break connection,
+                Err(err) => {
+                    let err = ReaderError::from(err);
+                    if !err.is_transient() {
+                        return Err(err);
+                    }
+                    watcher.wait(deadline, None)?;
+                }
+            }
+        };
+
+        let generation = root_connection.queue().generation;
+        let current_connection = loop {
+            match Self::fetch_new_queue_connection(
+                &mut root_connection,
+                generation,
+                &options.segments,
+            ) {
+                Ok(connection) => break connection,
+                Err(err) if err.is_transient() => {
+                    // the first queue is announced right after it's created
+                    watcher.wait(deadline, Some(RECHECK_INTERVAL))?
+                }
+                Err(err) => return Err(err),
+            }
+        };
+
+        Ok(Self::attached(
+            prefix,
+            options,
+            root_connection,
+            current_connection,
+        ))
+    }
+
+    fn attached(
+        prefix: &str,
+        options: ReaderOptions,
+        root_connection: ReaderConnection<1_000>,
+        current_connection: ReaderConnection<QUEUE_SIZE>,
+    ) -> Self {
+        // the writer sets it before announcing the first queue
+        let generation = root_connection.queue().generation;
+        root_connection.queue().channel.set_reader_pid();
+
+        Self {
+            root_connection,
+            current_connection,
+            generation,
+            prefix: prefix.to_string(),
+            options,
+            latency: LatencyHistogram::default(),
+            sequence: SequenceTracker::default(),
+            reassembly: Reassembly::default(),
+            next_restart_check: Instant::now(),
+            writer_gone: false,
+        }
+    }
+
+    // See `TypedWriter`, 0 if the writer pushes plain bytes
+    pub(crate) fn type_fingerprint(&self) -> u64 {
+        self.root_connection.queue().channel.type_fingerprint()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // Found out at most RECHECK_INTERVAL after it's happened, see `ready`
+    pub(crate) fn is_writer_gone(&self) -> bool {
+        self.writer_gone
     }
 
     fn fetch_new_queue_connection<const ROOT_QUEUE_SIZE: usize>(
         root_connection: &mut ReaderConnection<ROOT_QUEUE_SIZE>,
+        generation: u64,
+        segments: &SegmentOptions,
     ) -> Result<ReaderConnection<QUEUE_SIZE>, ReaderError> {
         let root_queue = root_connection.queue();
-        if let Some(queue_name) = root_queue.pop() {
-            Ok(ReaderConnection::new(ConnectionType::exact(&queue_name))?)
-        } else {
-            Err(ReaderError::FailedToGetNextQueue)
+        let announcement = root_queue
+            .pop_wrapping()
+            .ok_or(ReaderError::FailedToGetNextQueue)?;
+        let (epoch, queue_name) = announcement
+            .split_first_chunk::<8>()
+            .ok_or(ReaderError::FailedToGetNextQueue)?;
+        let epoch = u64::from_le_bytes(*epoch);
+
+        let connection =
+            ReaderConnection::with_options(ConnectionType::exact(queue_name), segments)?;
+
+        // every writer names its segments the same way and starts from
+        // epoch 0, so a restarted one may have replaced the announced segment
+        let found = connection.queue().generation;
+        if found != generation {
+            return Err(ReaderError::QueueGenerationMismatch {
+                expected: generation,
+                found,
+            });
+        }
+
+        // segments are recycled under the same name,
+        // the epoch tells which use of the segment has been announced
+        let found = connection.queue().epoch;
+        if found != epoch {
+            return Err(ReaderError::QueueEpochMismatch {
+                expected: epoch,
+                found,
+            });
         }
+        root_queue.channel.record_reader_segment(epoch);
+
+        Ok(connection)
     }
 
     pub fn ipc_pop(&mut self) -> Result<Option<Vec<u8>>, ReaderError> {
-        let mut current_queue = self.current_connection.queue();
-        if current_queue.done_reading || !current_queue.can_pop() {
-            // This queue is over
-            self.current_connection = Self::fetch_new_queue_connection(&mut self.root_connection)?;
-            current_queue = self.current_connection.queue();
+        Ok(self.ipc_pop_message()?.map(|message| message.payload))
+    }
+
+    // Same as `ipc_pop`, along with the push timestamp if the writer records them
+    pub fn ipc_pop_message(&mut self) -> Result<Option<Message>, ReaderError> {
+        Ok(self.pop_ref()?.map(|message| message.to_message()))
+    }
+
+    // Same as `ipc_pop`, but the message is copied into `buffer` (which is
+    // cleared first), so its allocation can be reused from one pop to the next.
+    // Returns false if there is nothing to pop
+    pub fn pop_into(&mut self, buffer: &mut Vec<u8>) -> Result<bool, ReaderError> {
+        buffer.clear();
+        match self.pop_ref()? {
+            Some(message) => {
+                buffer.extend_from_slice(&message);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // The message is borrowed right from shared memory and only
+    // released to the writer (and popped for good) when the guard is dropped
+    pub fn pop_ref(&mut self) -> Result<Option<MessageRef<'_, QUEUE_SIZE>>, ReaderError> {
+        loop {
+            if !self.ready()? {
+                return Ok(None);
+            }
+            let queue = self.current_connection.queue();
+            let (frame, next) = match queue.peek() {
+                Some(peeked) => peeked,
+                None => return Ok(None),
+            };
+
+            if frame.kind == Kind::Blob {
+                self.reassembly.abandon();
+                return match self.open_blob(frame.sequence, frame.payload) {
+                    Ok(blob) => Ok(Some(
+                        MessageRef::new(
+                            queue,
+                            next,
+                            &[],
+                            frame.sequence,
+                            frame.timestamp,
+                            self.tally(),
+                        )
+                        .with_blob(blob),
+                    )),
+                    Err(err) => {
+                        queue.advance(next);
+                        let len = Descriptor::decode(frame.payload)
+                            .map_or(0, |descriptor| descriptor.len);
+                        self.popped(frame.sequence, frame.timestamp, len);
+                        Err(err)
+                    }
+                };
+            }
+
+            if frame.kind == Kind::Whole {
+                self.reassembly.abandon();
+                return Ok(Some(MessageRef::new(
+                    queue,
+                    next,
+                    frame.payload,
+                    frame.sequence,
+                    frame.timestamp,
+                    self.tally(),
+                )));
+            }
+
+            match self.reassembly.push(&frame, self.options.max_message_size) {
+                Step::Pending => queue.advance(next),
+                Step::Complete {
+                    sequence,
+                    timestamp,
+                } => {
+                    // the reassembly buffer is borrowed along with the rest
+                    let tally = Tally {
+                        channel: &self.root_connection.queue().channel,
+                        sequence: &mut self.sequence,
+                        latency: &mut self.latency,
+                        generation: self.generation,
+                    };
+                    return Ok(Some(MessageRef::new(
+                        queue,
+                        next,
+                        self.reassembly.message(),
+                        sequence,
+                        timestamp,
+                        tally,
+                    )));
+                }
+                Step::TooLarge { sequence, len } => {
+                    queue.advance(next);
+                    self.popped(sequence, None, len);
+                    return Err(ReaderError::MessageTooLarge {
+                        sequence,
+                        len,
+                        max: self.options.max_message_size,
+                    });
+                }
+            }
+        }
+    }
+
+    // Appends up to `max` messages to `messages`, every queue is drained
+    // with a single load of its end and a single release of what's popped.
+    // Returns how many have been appended. If there's an error,
+    // the messages popped before it are appended all the same
+    pub fn pop_batch(
+        &mut self,
+        max: usize,
+        messages: &mut Vec<Message>,
+    ) -> Result<usize, ReaderError> {
+        let len = messages.len();
+        while messages.len() - len < max {
+            // a restart is only checked for while there's nothing to return
+            let ready = if messages.len() == len {
+                self.ready()?
+            } else {
+                self.rotate()?
+            };
+            if !ready {
+                break;
+            }
+
+            let from = messages.len();
+            self.current_connection
+                .queue()
+                .drain(max - (from - len), |frame| {
+                    messages.push(Message::from_frame(&frame))
+                });
+            for message in &messages[from..] {
+                self.popped(message.sequence, message.timestamp, message.payload.len());
+            }
+
+            // the next one is fragmented
+            if messages.len() == from {
+                match self.pop_ref()? {
+                    Some(message) => messages.push(message.to_message()),
+                    None => break,
+                }
+            }
+        }
+        Ok(messages.len() - len)
+    }
+
+    // The segment is unlinked as soon as it's mapped, so it's gone
+    // even if it turns out to be broken
+    fn open_blob(&self, sequence: u64, descriptor: &[u8]) -> Result<Blob, ReaderError> {
+        let connection_type = ConnectionType::blob(self.generation, sequence, &self.prefix);
+        let checksum_mismatch = || ReaderError::ChecksumMismatch {
+            sequence,
+            segment: connection_type.name(),
+        };
+
+        let descriptor = Descriptor::decode(descriptor).ok_or_else(checksum_mismatch)?;
+        let blob = Blob::open(&connection_type, descriptor.len)?;
+        if blob::checksum(blob.data()) != descriptor.checksum {
+            return Err(checksum_mismatch());
+        }
+        Ok(blob)
+    }
+
+    fn tally(&mut self) -> Tally<'_> {
+        Tally {
+            channel: &self.root_connection.queue().channel,
+            sequence: &mut self.sequence,
+            latency: &mut self.latency,
+            generation: self.generation,
+        }
+    }
+
+    fn popped(&mut self, sequence: u64, timestamp: Option<u64>, bytes: usize) {
+        self.tally().record(sequence, timestamp, bytes);
+    }
+
+    // Push to pop latency of every timestamped message popped so far
+    // (or since the last `reset_latency`)
+    pub fn latency(&self) -> LatencySummary {
+        self.latency.summary()
+    }
+
+    pub fn reset_latency(&mut self) {
+        self.latency = LatencyHistogram::default();
+    }
+
+    // Gaps and duplicates in the sequence numbers seen so far,
+    // including messages lost to writer restarts
+    pub fn sequence_report(&self) -> SequenceReport {
+        self.sequence.report()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.root_connection.queue().channel.snapshot(
+            2,
+            ReaderConnection::<1_000>::SIZE + ReaderConnection::<QUEUE_SIZE>::SIZE,
+        )
+    }
+
+    // Whether there's a message to pop, checks for a writer restart if not.
+    // That takes an fstat, so a reader spinning on an empty queue
+    // only does it every RECHECK_INTERVAL
+    fn ready(&mut self) -> Result<bool, ReaderError> {
+        if self.rotate()? {
+            return Ok(true);
+        }
+        let now = Instant::now();
+        if now >= self.next_restart_check {
+            self.next_restart_check = now + RECHECK_INTERVAL;
+            self.reattach_if_writer_restarted()?;
+        }
+        Ok(false)
+    }
+
+    // Whether there's a message to pop in the current queue,
+    // moves on to the next queue once the current one is over
+    fn rotate(&mut self) -> Result<bool, ReaderError> {
+        let current_queue = self.current_connection.queue();
+        let done_writing = current_queue.is_done_writing();
+        if current_queue.peek().is_some() {
+            return Ok(true);
+        }
+        if !done_writing {
+            return Ok(false);
+        }
+
+        // This queue is over
+        match Self::fetch_new_queue_connection(
+            &mut self.root_connection,
+            self.generation,
+            &self.options.segments,
+        ) {
+            Ok(connection) => {
+                current_queue.mark_done_reading();
+                self.current_connection = connection;
+                Ok(self.current_connection.queue().peek().is_some())
+            }
+            // the writer has restarted before its last queue was picked up,
+            // the segment of that name is the new writer's
+            Err(ReaderError::QueueGenerationMismatch { .. }) => {
+                self.reattach_if_writer_restarted()?;
+                Ok(false)
+            }
+            // the writer is gone, its last queue was either never announced
+            // or has been unlinked before we got to it
+            Err(err) if err.is_transient() => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Returns `WriterRestarted` once the reader is attached to a new writer,
+    // everything that the previous writer didn't manage to hand over is lost
+    fn reattach_if_writer_restarted(&mut self) -> Result<(), ReaderError> {
+        if !self.root_connection.is_unlinked()? {
+            return Ok(());
+        }
+        // until a new one is there
+        self.writer_gone = true;
+
+        let mut root_connection = match ReaderConnection::new(ConnectionType::root(&self.prefix)) {
+            Ok(connection) => connection,
+            Err(err) => {
+                let err = ReaderError::from(err);
+                return if err.is_transient() { Ok(()) } else { Err(err) };
+            }
+        };
+        let generation = root_connection.queue().generation;
+        let current_connection = match Self::fetch_new_queue_connection(
+            &mut root_connection,
+            generation,
+            &self.options.segments,
+        ) {
+            Ok(connection) => connection,
+            Err(err) if err.is_transient() => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        let previous_generation = self.generation;
+        let pushed = self.root_connection.queue().channel.messages_pushed();
+        if let Some(gap) = self.sequence.restarted(previous_generation, pushed) {
+            event!(
+                warn,
+                "reader.sequence_gap",
+                generation = gap.generation,
+                expected = gap.expected,
+                found = gap.found,
+            );
+        }
+
+        let latency = std::mem::take(&mut self.latency);
+        let sequence = std::mem::take(&mut self.sequence);
+        // a message the previous writer didn't finish is dropped along with it
+        *self = Self::attached(
+            &self.prefix,
+            self.options.clone(),
+            root_connection,
+            current_connection,
+        );
+        self.latency = latency;
+        self.sequence = sequence;
+        event!(
+            info,
+            "reader.writer_restarted",
+            previous_generation = previous_generation,
+            generation = self.generation,
+        );
+
+        Err(ReaderError::WriterRestarted {
+            previous_generation,
+            generation: self.generation,
+        })
+    }
+}
+
+// Everything a message counts towards once it's popped for good,
+// which for a `MessageRef` is only when it's dropped
+pub(crate) struct Tally<'a> {
+    channel: &'a ChannelHeader,
+    sequence: &'a mut SequenceTracker,
+    latency: &'a mut LatencyHistogram,
+    generation: u64,
+}
+
+impl Tally<'_> {
+    pub(crate) fn record(self, sequence: u64, timestamp: Option<u64>, bytes: usize) {
+        self.channel.record_pop(bytes);
+        match self.sequence.check(self.generation, sequence) {
+            Check::InOrder => {}
+            Check::Gap(gap) => event!(
+                warn,
+                "reader.sequence_gap",
+                generation = gap.generation,
+                expected = gap.expected,
+                found = gap.found,
+            ),
+            Check::Duplicate => event!(
+                warn,
+                "reader.sequence_duplicate",
+                generation = self.generation,
+                sequence = sequence,
+            ),
         }
+        if let Some(timestamp) = timestamp {
+            self.latency
+                .record(monotonic_nanos().saturating_sub(timestamp));
+        }
+    }
+}
 
-        Ok(current_queue.pop())
+impl<const QUEUE_SIZE: usize> Drop for Reader<QUEUE_SIZE> {
+    fn drop(&mut self) {
+        self.root_connection.queue().channel.clear_reader_pid();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Writer;
+    use crate::{Layout, Writer, WriterOptions};
 
     #[test]
     fn test_reader() {
         let prefix = crate::random_name();
 
-        let mut writer = Writer::<20>::new(&prefix).unwrap();
-        let mut reader = Reader::<20>::new(&prefix).unwrap();
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let mut reader = Reader::<38>::new(&prefix).unwrap();
 
         // queue 1
         writer.ipc_push(b"111111111").unwrap();
@@ -70,4 +582,651 @@ mod tests {
         writer.ipc_push(b"333333333").unwrap();
         assert_eq!(reader.ipc_pop().unwrap(), Some(b"333333333".to_vec()));
     }
+
+    #[test]
+    fn test_recycled_queue() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let mut reader = Reader::<38>::new(&prefix).unwrap();
+
+        // queue 1
+        writer.ipc_push(b"111111111").unwrap();
+        writer.ipc_push(b"222222222").unwrap();
+        // queue 2
+        writer.ipc_push(b"333333333").unwrap();
+        writer.ipc_push(b"444444444").unwrap();
+
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"111111111".to_vec()));
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"222222222".to_vec()));
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"333333333".to_vec()));
+
+        // queue 1 is consumed and gets reused as queue 3,
+        // its old messages must not show up again
+        writer.ipc_push(b"555555555").unwrap();
+
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"444444444".to_vec()));
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"555555555".to_vec()));
+        assert_eq!(
+            reader.current_connection.id(),
+            ConnectionType::worker(0, &prefix).id()
+        );
+        assert_eq!(reader.current_connection.queue().epoch, 2);
+        assert_eq!(
+            reader.current_connection.queue().generation,
+            writer.generation()
+        );
+        assert_eq!(reader.ipc_pop().unwrap(), None);
+    }
+
+    #[test]
+    fn test_long_running() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let mut reader = Reader::<38>::new(&prefix).unwrap();
+
+        // enough rotations for the root queue to wrap around many times
+        for i in 0..5_000 {
+            let message = format!("{:09}", i);
+            writer.ipc_push(message.as_bytes()).unwrap();
+            assert_eq!(reader.ipc_pop().unwrap(), Some(message.into_bytes()));
+        }
+        assert_eq!(reader.ipc_pop().unwrap(), None);
+    }
+
+    #[test]
+    fn test_stats() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let mut reader = Reader::<38>::new(&prefix).unwrap();
+
+        writer.ipc_push(b"111111111").unwrap();
+        writer.ipc_push(b"222222222").unwrap();
+        writer.ipc_push(b"333333333").unwrap();
+        writer.ipc_push(b"4444").unwrap();
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"111111111".to_vec()));
+
+        // both sides see the same shared counters
+        let stats = reader.stats();
+        let writer_stats = writer.stats();
+        assert_eq!(
+            Stats {
+                mapped_segments: 0,
+                mapped_bytes: 0,
+                ..stats
+            },
+            Stats {
+                mapped_segments: 0,
+                mapped_bytes: 0,
+                ..writer_stats
+            }
+        );
+        assert_eq!(stats.messages_pushed, 4);
+        assert_eq!(stats.bytes_pushed, 31);
+        assert_eq!(stats.messages_popped, 1);
+        assert_eq!(stats.bytes_popped, 9);
+        assert_eq!(stats.depth_messages, 3);
+        assert_eq!(stats.depth_bytes, 22);
+        assert_eq!(stats.rotations, 1);
+        assert_eq!(stats.writer_segment, 1);
+        assert_eq!(stats.reader_segment, 0);
+        assert_eq!(stats.live_segments, 2);
+        assert_eq!(stats.mapped_segments, 2);
+
+        assert_eq!(writer_stats.mapped_segments, 3);
+
+        assert!(reader.ipc_pop().unwrap().is_some());
+        assert!(reader.ipc_pop().unwrap().is_some());
+
+        let stats = reader.stats();
+        assert_eq!(stats.depth_messages, 1);
+        assert_eq!(stats.reader_segment, 1);
+        assert_eq!(stats.live_segments, 1);
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let prefix = crate::random_name();
+
+        let options = WriterOptions {
+            timestamps: true,
+            ..WriterOptions::default()
+        };
+        let mut writer = Writer::<54>::with_options(&prefix, options).unwrap();
+        let mut reader = Reader::<54>::new(&prefix).unwrap();
+
+        let before = monotonic_nanos();
+        writer.ipc_push(b"111111111").unwrap();
+        writer.ipc_push(b"222222222").unwrap();
+        // 2 frames of 27 bytes per queue
+        writer.ipc_push(b"333333333").unwrap();
+        let after = monotonic_nanos();
+
+        let first = reader.ipc_pop_message().unwrap().unwrap();
+        assert_eq!(first.payload, b"111111111");
+        let timestamp = first.timestamp.unwrap();
+        assert!(before <= timestamp && timestamp <= after);
+
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"222222222".to_vec()));
+        let third = reader.ipc_pop_message().unwrap().unwrap();
+        assert!(third.timestamp.unwrap() >= timestamp);
+        assert_eq!(writer.stats().rotations, 1);
+
+        let latency = reader.latency();
+        assert_eq!(latency.count, 3);
+        assert!(latency.p50 <= latency.p99 && latency.p99 <= latency.max);
+
+        reader.reset_latency();
+        assert_eq!(reader.latency().count, 0);
+    }
+
+    #[test]
+    fn test_no_timestamps() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let mut reader = Reader::<38>::new(&prefix).unwrap();
+
+        writer.ipc_push(b"111111111").unwrap();
+        let message = reader.ipc_pop_message().unwrap().unwrap();
+        assert_eq!(message.timestamp, None);
+        assert_eq!(reader.latency().count, 0);
+    }
+
+    #[test]
+    fn test_sequence_numbers() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let mut reader = Reader::<38>::new(&prefix).unwrap();
+
+        // carried on across segments
+        for _ in 0..5 {
+            writer.ipc_push(b"111111111").unwrap();
+        }
+        for sequence in 0..5 {
+            let message = reader.ipc_pop_message().unwrap().unwrap();
+            assert_eq!(message.sequence, sequence);
+        }
+        assert_eq!(reader.sequence_report(), SequenceReport::default());
+
+        writer.ipc_push(b"222222222").unwrap();
+        // lost with the writer before the reader gets to them
+        writer.ipc_push(b"222222222").unwrap();
+        writer.ipc_push(b"222222222").unwrap();
+        assert_eq!(reader.ipc_pop_message().unwrap().unwrap().sequence, 5);
+        let previous_generation = writer.generation();
+        drop(writer);
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        writer.ipc_push(b"333333333").unwrap();
+        // the old writer's next queue is unlinked before the reader gets to it
+        assert!(matches!(
+            reader.ipc_pop(),
+            Err(ReaderError::WriterRestarted { .. })
+        ));
+        assert_eq!(reader.ipc_pop_message().unwrap().unwrap().sequence, 0);
+
+        assert_eq!(
+            reader.sequence_report(),
+            SequenceReport {
+                gaps: 1,
+                missed: 2,
+                duplicates: 0,
+                last_gap: Some(SequenceGap {
+                    generation: previous_generation,
+                    expected: 6,
+                    found: 8,
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn test_pop_ref() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let mut reader = Reader::<38>::new(&prefix).unwrap();
+
+        writer.ipc_push(b"111111111").unwrap();
+        writer.ipc_push(b"222222222").unwrap();
+        writer.ipc_push(b"333333333").unwrap();
+
+        let message = reader.pop_ref().unwrap().unwrap();
+        assert_eq!(&*message, b"111111111");
+        assert_eq!(message.sequence(), 0);
+        assert_eq!(message.timestamp(), None);
+        drop(message);
+
+        // not released, so it's still there, and only popped once
+        let message = reader.pop_ref().unwrap().unwrap();
+        assert_eq!(message.payload(), b"222222222");
+        std::mem::forget(message);
+        assert_eq!(reader.stats().messages_popped, 1);
+        let message = reader.pop_ref().unwrap().unwrap();
+        assert_eq!(message.payload(), b"222222222");
+        drop(message);
+        assert_eq!(reader.stats().messages_popped, 2);
+        assert_eq!(reader.sequence_report(), SequenceReport::default());
+
+        // on to the next queue
+        assert_eq!(&*reader.pop_ref().unwrap().unwrap(), b"333333333");
+        assert!(reader.pop_ref().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_pop_into() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let mut reader = Reader::<38>::new(&prefix).unwrap();
+
+        writer.ipc_push(b"111111111").unwrap();
+        writer.ipc_push(b"22").unwrap();
+
+        let mut buffer = Vec::with_capacity(255);
+        let capacity = buffer.capacity();
+        assert!(reader.pop_into(&mut buffer).unwrap());
+        assert_eq!(buffer, b"111111111");
+        assert!(reader.pop_into(&mut buffer).unwrap());
+        assert_eq!(buffer, b"22");
+        assert!(!reader.pop_into(&mut buffer).unwrap());
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.capacity(), capacity);
+
+        assert_eq!(reader.stats().messages_popped, 2);
+    }
+
+    #[test]
+    fn test_pop_batch() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let mut reader = Reader::<38>::new(&prefix).unwrap();
+
+        let mut messages = vec![];
+        assert_eq!(reader.pop_batch(10, &mut messages).unwrap(), 0);
+
+        writer
+            .push_batch(&[b"111111111", b"222222222", b"333333333", b"444444444", b"5"])
+            .unwrap();
+
+        // across queues
+        assert_eq!(reader.pop_batch(3, &mut messages).unwrap(), 3);
+        assert_eq!(reader.pop_batch(10, &mut messages).unwrap(), 2);
+        assert_eq!(reader.pop_batch(10, &mut messages).unwrap(), 0);
+        assert_eq!(
+            messages
+                .iter()
+                .map(|message| (message.payload.as_slice(), message.sequence))
+                .collect::<Vec<_>>(),
+            vec![
+                (&b"111111111"[..], 0),
+                (b"222222222", 1),
+                (b"333333333", 2),
+                (b"444444444", 3),
+                (b"5", 4),
+            ]
+        );
+
+        let stats = reader.stats();
+        assert_eq!(stats.messages_popped, 5);
+        assert_eq!(stats.depth_messages, 0);
+        assert_eq!(stats.reader_segment, 2);
+        assert_eq!(reader.sequence_report(), SequenceReport::default());
+    }
+
+    #[test]
+    fn test_aligned_layout() {
+        for (layout, align) in [(Layout::Aligned8, 8), (Layout::Aligned64, 64)] {
+            let prefix = crate::random_name();
+
+            let options = WriterOptions {
+                layout,
+                ..WriterOptions::default()
+            };
+            let mut writer = Writer::<128>::with_options(&prefix, options).unwrap();
+            let mut reader = Reader::<128>::new(&prefix).unwrap();
+
+            writer.ipc_push(b"111111111").unwrap();
+            writer
+                .push_batch(&[b"222222222", b"333333333", b"4"])
+                .unwrap();
+            // fragments are aligned too
+            writer.ipc_push(&[5; 200]).unwrap();
+            writer.ipc_push(b"").unwrap();
+
+            for expected in [&b"111111111"[..], b"222222222", b"333333333", b"4"] {
+                let message = reader.pop_ref().unwrap().unwrap();
+                assert_eq!(&*message, expected);
+                assert_eq!(message.as_ptr() as usize % align, 0);
+            }
+            assert_eq!(reader.ipc_pop().unwrap(), Some(vec![5; 200]));
+            assert_eq!(reader.ipc_pop().unwrap(), Some(vec![]));
+            assert_eq!(reader.ipc_pop().unwrap(), None);
+            assert_eq!(reader.sequence_report(), SequenceReport::default());
+        }
+    }
+
+    #[test]
+    fn test_segment_options() {
+        let prefix = crate::random_name();
+
+        let segments = crate::SegmentOptions {
+            prefault: crate::Prefault::Touch,
+            lock: true,
+            advice: None,
+        };
+        let options = WriterOptions {
+            segments,
+            ..WriterOptions::default()
+        };
+        // every segment is locked on both sides, see `mlock_refused`
+        let mut writer = match Writer::<38>::with_options(&prefix, options) {
+            Ok(writer) => writer,
+            Err(err) if crate::segment::mlock_refused(&err) => return,
+            Err(err) => panic!("{:?}", err),
+        };
+        let options = ReaderOptions {
+            segments,
+            ..ReaderOptions::default()
+        };
+        let mut reader = match Reader::<38>::with_options(&prefix, options) {
+            Ok(reader) => reader,
+            Err(err) if crate::segment::mlock_refused(&err) => return,
+            Err(err) => panic!("{:?}", err),
+        };
+
+        for message in [b"111111111", b"222222222", b"333333333"] {
+            writer.ipc_push(message).unwrap();
+        }
+        for message in [b"111111111", b"222222222", b"333333333"] {
+            assert_eq!(reader.ipc_pop().unwrap(), Some(message.to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_fragmented() {
+        let prefix = crate::random_name();
+
+        let options = WriterOptions {
+            timestamps: true,
+            ..WriterOptions::default()
+        };
+        let mut writer = Writer::<54>::with_options(&prefix, options).unwrap();
+        let mut reader = Reader::<54>::new(&prefix).unwrap();
+
+        let large: Vec<u8> = (0..100).collect();
+        writer.ipc_push(b"111111111").unwrap();
+        writer.ipc_push(&large).unwrap();
+        writer.ipc_push(b"333333333").unwrap();
+        assert!(writer.stats().rotations >= 3);
+
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"111111111".to_vec()));
+        let message = reader.ipc_pop_message().unwrap().unwrap();
+        assert_eq!(message.payload, large);
+        assert_eq!(message.sequence, 1);
+        assert!(message.timestamp.is_some());
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"333333333".to_vec()));
+        assert_eq!(reader.ipc_pop().unwrap(), None);
+
+        let stats = reader.stats();
+        assert_eq!(stats.messages_popped, 3);
+        assert_eq!(stats.depth_messages, 0);
+        assert_eq!(stats.depth_bytes, 0);
+        assert_eq!(reader.latency().count, 3);
+        assert_eq!(reader.sequence_report(), SequenceReport::default());
+    }
+
+    #[test]
+    fn test_fragmented_batch() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let mut reader = Reader::<38>::new(&prefix).unwrap();
+
+        writer
+            .push_batch(&[b"1", &[b'2'; 50], b"3", &[b'4'; 50]])
+            .unwrap();
+
+        let mut messages = vec![];
+        assert_eq!(reader.pop_batch(10, &mut messages).unwrap(), 4);
+        assert_eq!(
+            messages
+                .iter()
+                .map(|message| (message.payload.clone(), message.sequence))
+                .collect::<Vec<_>>(),
+            vec![
+                (b"1".to_vec(), 0),
+                (vec![b'2'; 50], 1),
+                (b"3".to_vec(), 2),
+                (vec![b'4'; 50], 3),
+            ]
+        );
+        assert_eq!(reader.pop_batch(10, &mut messages).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_fragmented_too_large() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let options = ReaderOptions {
+            max_message_size: 64,
+            ..ReaderOptions::default()
+        };
+        let mut reader = Reader::<38>::with_options(&prefix, options).unwrap();
+
+        writer.ipc_push(&[b'1'; 64]).unwrap();
+        writer.ipc_push(&[b'2'; 65]).unwrap();
+        writer.ipc_push(b"333333333").unwrap();
+
+        assert_eq!(reader.ipc_pop().unwrap(), Some(vec![b'1'; 64]));
+        assert!(matches!(
+            reader.ipc_pop(),
+            Err(ReaderError::MessageTooLarge {
+                sequence: 1,
+                len: 65,
+                max: 64,
+            })
+        ));
+        // the rest of it is skipped
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"333333333".to_vec()));
+        assert_eq!(reader.ipc_pop().unwrap(), None);
+
+        assert_eq!(reader.stats().depth_messages, 0);
+        assert_eq!(reader.sequence_report(), SequenceReport::default());
+    }
+
+    #[test]
+    fn test_push_large() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let mut reader = Reader::<38>::new(&prefix).unwrap();
+
+        let large: Vec<u8> = (0..1 << 20).map(|i| i as u8).collect();
+        writer.ipc_push(b"1").unwrap();
+        writer.push_large(&large).unwrap();
+        writer.push_large(b"").unwrap();
+        writer.ipc_push(b"4").unwrap();
+        let blob = ConnectionType::blob(writer.generation(), 1, &prefix);
+
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"1".to_vec()));
+        {
+            let message = reader.pop_ref().unwrap().unwrap();
+            assert_eq!(message.payload(), large);
+            assert_eq!(message.sequence(), 1);
+            // already unlinked, it goes away with the mapping
+            assert!(!crate::blob::unlink(&blob).unwrap());
+        }
+        assert_eq!(reader.ipc_pop().unwrap(), Some(vec![]));
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"4".to_vec()));
+        assert_eq!(reader.ipc_pop().unwrap(), None);
+
+        let stats = reader.stats();
+        assert_eq!(stats.messages_popped, 4);
+        assert_eq!(stats.depth_bytes, 0);
+        assert_eq!(reader.sequence_report(), SequenceReport::default());
+    }
+
+    #[test]
+    fn test_push_large_checksum_mismatch() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let mut reader = Reader::<38>::new(&prefix).unwrap();
+
+        writer.push_large(b"111111111").unwrap();
+        writer.ipc_push(b"2").unwrap();
+        // replaced by somebody else
+        let blob = ConnectionType::blob(writer.generation(), 0, &prefix);
+        crate::blob::create(&blob, b"xxxxxxxxx").unwrap();
+
+        let err = reader.ipc_pop().unwrap_err();
+        assert!(matches!(
+            &err,
+            ReaderError::ChecksumMismatch { sequence: 0, segment } if *segment == blob.name()
+        ));
+        assert!(!crate::blob::unlink(&blob).unwrap());
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"2".to_vec()));
+    }
+
+    #[test]
+    fn test_writer_restart() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let mut reader = Reader::<38>::new(&prefix).unwrap();
+        let first_generation = reader.generation();
+        assert_eq!(first_generation, writer.generation());
+
+        writer.ipc_push(b"111111111").unwrap();
+        writer.ipc_push(b"222222222").unwrap();
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"111111111".to_vec()));
+        drop(writer);
+
+        // no writer, but what's already mapped can still be read
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"222222222".to_vec()));
+        assert_eq!(reader.ipc_pop().unwrap(), None);
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        writer.ipc_push(b"333333333").unwrap();
+
+        // the last check was just now, when the queue turned out to be empty
+        assert_eq!(reader.ipc_pop().unwrap(), None);
+        std::thread::sleep(RECHECK_INTERVAL);
+
+        let generation = writer.generation();
+        assert!(matches!(
+            reader.ipc_pop(),
+            Err(ReaderError::WriterRestarted {
+                previous_generation,
+                generation: new_generation,
+            }) if previous_generation == first_generation && new_generation == generation
+        ));
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"333333333".to_vec()));
+        assert_eq!(reader.ipc_pop().unwrap(), None);
+    }
+
+    #[test]
+    fn test_writer_crash() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let mut reader = Reader::<38>::new(&prefix).unwrap();
+
+        writer.ipc_push(b"111111111").unwrap();
+        writer.crash();
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"111111111".to_vec()));
+        assert_eq!(reader.ipc_pop().unwrap(), None);
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        writer.ipc_push(b"222222222").unwrap();
+        std::thread::sleep(RECHECK_INTERVAL);
+
+        assert!(matches!(
+            reader.ipc_pop(),
+            Err(ReaderError::WriterRestarted { .. })
+        ));
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"222222222".to_vec()));
+    }
+
+    #[test]
+    fn test_writer_restart_before_rotation() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let mut reader = Reader::<38>::new(&prefix).unwrap();
+        let first_generation = reader.generation();
+
+        // queue 1
+        writer.ipc_push(b"111111111").unwrap();
+        writer.ipc_push(b"222222222").unwrap();
+        // queue 2, announced in the root the reader still holds
+        writer.ipc_push(b"333333333").unwrap();
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"111111111".to_vec()));
+        writer.crash();
+
+        // same segment names and epochs
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        for message in [b"444444444", b"555555555", b"666666666"] {
+            writer.ipc_push(message).unwrap();
+        }
+
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"222222222".to_vec()));
+        let generation = writer.generation();
+        assert!(matches!(
+            reader.ipc_pop(),
+            Err(ReaderError::WriterRestarted {
+                previous_generation,
+                generation: new_generation,
+            }) if previous_generation == first_generation && new_generation == generation
+        ));
+        for message in [b"444444444", b"555555555", b"666666666"] {
+            assert_eq!(reader.ipc_pop().unwrap(), Some(message.to_vec()));
+        }
+        assert_eq!(reader.ipc_pop().unwrap(), None);
+    }
+
+    #[test]
+    fn test_connect_wait() {
+        let prefix = crate::random_name();
+        let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+
+        let writer_thread = {
+            let prefix = prefix.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                let mut writer = Writer::<38>::new(&prefix).unwrap();
+                writer.ipc_push(b"111111111").unwrap();
+                done_rx.recv().unwrap();
+            })
+        };
+
+        let mut reader = Reader::<38>::connect_wait(&prefix, Duration::from_secs(5)).unwrap();
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"111111111".to_vec()));
+
+        done_tx.send(()).unwrap();
+        writer_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_connect_wait_timeout() {
+        let prefix = crate::random_name();
+
+        let err = Reader::<38>::connect_wait(&prefix, Duration::from_millis(20))
+            .map(|_| ())
+            .unwrap_err();
+
+        assert!(matches!(err, ReaderError::Timeout));
+        assert_eq!(err.to_string(), "timed out waiting for the writer");
+        assert_eq!(
+            std::io::Error::from(err).kind(),
+            std::io::ErrorKind::TimedOut
+        );
+    }
 }
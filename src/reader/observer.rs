@@ -0,0 +1,468 @@
+use crate::{
+    blob::Descriptor,
+    frame::{Frame, Kind, FRAGMENTED_LEN},
+    reader::{
+        queue::{frames, Queue},
+        reassembly::{Reassembly, Step},
+        ReaderConnection, ReaderError,
+    },
+    shm_dir::worker_numbers,
+    ConnectionType, Stats,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueState {
+    pub start: usize,
+    pub end: usize,
+    pub done_reading: bool,
+    pub done_writing: bool,
+    // a fragmented message counts once, with all of its bytes,
+    // in the segment its first fragment is in
+    pub pending_messages: usize,
+    pub pending_bytes: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentState {
+    pub name: String,
+    pub epoch: u64,
+    // bytes, header included
+    pub size: usize,
+    pub queue: QueueState,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelState {
+    pub generation: u64,
+    // see `TypedWriter`, 0 for plain bytes
+    pub type_fingerprint: u64,
+    // 0 if nobody is attached
+    pub writer_pid: u32,
+    pub reader_pid: u32,
+    pub stats: Stats,
+    // queues that are announced, but not picked up by the reader yet
+    pub announcements: QueueState,
+    // every worker segment of the channel (including pooled ones), oldest first
+    pub segments: Vec<SegmentState>,
+}
+
+// Where an observer following a channel has got to,
+// the default one is the very beginning of the channel
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Position {
+    epoch: u64,
+    offset: usize,
+}
+
+// Maps the channel read-only and never moves any cursor,
+// so it can look at a channel that is in use by a reader.
+// Segments are mapped whole, so it doesn't need to know their size
+pub struct Observer {
+    root_connection: ReaderConnection<1_000>,
+    prefix: String,
+}
+
+impl Observer {
+    pub fn attach(prefix: &str) -> Result<Self, ReaderError> {
+        let root_connection = ReaderConnection::read_only(ConnectionType::root(prefix))?;
+        Ok(Self {
+            root_connection,
+            prefix: prefix.to_string(),
+        })
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn state(&self) -> Result<ChannelState, ReaderError> {
+        let root_queue = self.root_connection.queue_ref();
+        let segments = self.segments()?;
+
+        let stats = root_queue.channel.snapshot(
+            segments.len() + 1,
+            self.root_connection.size()
+                + segments.iter().map(ReaderConnection::size).sum::<usize>(),
+        );
+
+        let announcements = root_queue.pending_wrapping();
+        Ok(ChannelState {
+            generation: root_queue.generation,
+            type_fingerprint: root_queue.channel.type_fingerprint(),
+            writer_pid: root_queue.channel.writer_pid(),
+            reader_pid: root_queue.channel.reader_pid(),
+            stats,
+            announcements: queue_state(
+                root_queue,
+                announcements.len(),
+                announcements.iter().map(|message| message.len()).sum(),
+            ),
+            segments: segments
+                .iter()
+                .map(|connection| {
+                    let queue = connection.queue_ref();
+                    let (messages, bytes) = count(&pending(connection));
+                    SegmentState {
+                        name: connection.name(),
+                        epoch: queue.epoch,
+                        size: connection.size(),
+                        queue: queue_state(queue, messages, bytes),
+                    }
+                })
+                .collect(),
+        })
+    }
+
+    // Messages that are pushed but not popped yet, oldest first.
+    // A fragmented message the reader is half way through is left out
+    pub fn pending(&self) -> Result<Vec<Vec<u8>>, ReaderError> {
+        let mut collector = Collector::default();
+        for connection in self.segments()? {
+            let queue = connection.queue_ref();
+            if queue.is_done_reading() {
+                continue;
+            }
+            collector.collect(queue.epoch, queue.cursors().0, pending(&connection));
+        }
+        Ok(collector.messages)
+    }
+
+    // Position right after the last message pushed so far,
+    // following from there only shows what comes next
+    pub fn end(&self) -> Result<Position, ReaderError> {
+        let position = match self.segments()?.last() {
+            Some(connection) => {
+                let queue = connection.queue_ref();
+                Position {
+                    epoch: queue.epoch,
+                    offset: queue.cursors().1,
+                }
+            }
+            None => Position::default(),
+        };
+        Ok(position)
+    }
+
+    // Every message pushed after `position`, whether it's popped already or not,
+    // and moves `position` past them. A fragmented message that isn't all
+    // there yet is left for next time, `position` stops where it starts.
+    // Segments that are released in the meantime are skipped
+    pub fn read_from(&self, position: &mut Position) -> Result<Vec<Vec<u8>>, ReaderError> {
+        let mut collector = Collector::default();
+        let mut last = *position;
+        for connection in self.segments()? {
+            let queue = connection.queue_ref();
+            let offset = match queue.epoch {
+                epoch if epoch < position.epoch => continue,
+                epoch if epoch == position.epoch => position.offset,
+                _ => 0,
+            };
+            let end = queue.cursors().1;
+            collector.collect(
+                queue.epoch,
+                offset,
+                frames(connection.data(), queue.flags, offset, end),
+            );
+            last = Position {
+                epoch: queue.epoch,
+                offset: end.max(offset),
+            };
+        }
+        *position = collector.unfinished.unwrap_or(last);
+        Ok(collector.messages)
+    }
+
+    // The writer has gone away (or restarted), a new observer
+    // has to be attached to see what happens to the channel next
+    pub fn is_detached(&self) -> Result<bool, ReaderError> {
+        Ok(self.root_connection.is_unlinked()?)
+    }
+
+    fn segments(&self) -> Result<Vec<ReaderConnection<0>>, ReaderError> {
+        let mut connections = vec![];
+
+        for n in worker_numbers(&self.prefix).map_err(ReaderError::ShmDirError)? {
+            match ReaderConnection::read_only(ConnectionType::worker(n, &self.prefix)) {
+                Ok(connection) => connections.push(connection),
+                Err(err) => {
+                    // released (or just created) while we were looking
+                    let err = ReaderError::from(err);
+                    if !err.is_transient() {
+                        return Err(err);
+                    }
+                }
+            }
+        }
+
+        connections.sort_by_key(|connection| connection.queue_ref().epoch);
+        Ok(connections)
+    }
+}
+
+// Puts messages back together the way `Reader` does, the fragments
+// of one may be spread across segments. Payloads that are in
+// a segment of their own are left out
+#[derive(Default)]
+struct Collector {
+    reassembly: Reassembly,
+    messages: Vec<Vec<u8>>,
+    // where the fragmented message that isn't complete yet starts
+    unfinished: Option<Position>,
+}
+
+impl Collector {
+    fn collect(&mut self, epoch: u64, from: usize, frames: Vec<(Frame<'_>, usize)>) {
+        let mut at = from;
+        for (frame, next) in frames {
+            match frame.kind {
+                Kind::Whole | Kind::Blob => {
+                    self.reassembly.abandon();
+                    self.unfinished = None;
+                    if frame.kind == Kind::Whole {
+                        self.messages.push(frame.payload.to_vec());
+                    }
+                }
+                Kind::First | Kind::Middle | Kind::Last => {
+                    if frame.kind == Kind::First {
+                        self.unfinished = Some(Position { epoch, offset: at });
+                    }
+                    if let Step::Complete { .. } = self.reassembly.push(&frame, usize::MAX) {
+                        self.messages.push(self.reassembly.message().to_vec());
+                        self.unfinished = None;
+                    }
+                }
+            }
+            at = next;
+        }
+    }
+}
+
+fn pending(connection: &ReaderConnection<0>) -> Vec<(Frame<'_>, usize)> {
+    let queue = connection.queue_ref();
+    let (start, end) = queue.cursors();
+    frames(connection.data(), queue.flags, start, end)
+}
+
+// Messages that start in `frames` and how many bytes they are,
+// the same way `Stats` counts them
+fn count(frames: &[(Frame<'_>, usize)]) -> (usize, usize) {
+    frames.iter().fold((0, 0), |(messages, bytes), (frame, _)| {
+        let len = match frame.kind {
+            Kind::Whole => frame.payload.len(),
+            Kind::First => frame
+                .payload
+                .first_chunk::<FRAGMENTED_LEN>()
+                .map_or(0, |len| u64::from_le_bytes(*len) as usize),
+            Kind::Blob => Descriptor::decode(frame.payload).map_or(0, |blob| blob.len),
+            Kind::Middle | Kind::Last => return (messages, bytes),
+        };
+        (messages + 1, bytes + len)
+    })
+}
+
+fn queue_state<const N: usize>(
+    queue: &Queue<N>,
+    pending_messages: usize,
+    pending_bytes: usize,
+) -> QueueState {
+    let (start, end) = queue.cursors();
+    QueueState {
+        start,
+        end,
+        done_reading: queue.is_done_reading(),
+        done_writing: queue.is_done_writing(),
+        pending_messages,
+        pending_bytes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Reader, Writer};
+
+    #[test]
+    fn test_observer() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let mut reader = Reader::<38>::new(&prefix).unwrap();
+        let observer = Observer::attach(&prefix).unwrap();
+
+        // queue 1
+        writer.ipc_push(b"111111111").unwrap();
+        writer.ipc_push(b"\xff\xfe").unwrap();
+        // queue 2
+        writer.ipc_push(b"333333333").unwrap();
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"111111111".to_vec()));
+
+        let state = observer.state().unwrap();
+        assert_eq!(state.generation, writer.generation());
+        assert_eq!(state.writer_pid, std::process::id());
+        assert_eq!(state.reader_pid, std::process::id());
+        assert_eq!(state.stats.depth_messages, 2);
+        assert_eq!(state.announcements.pending_messages, 1);
+        assert_eq!(
+            state.segments,
+            vec![
+                SegmentState {
+                    name: format!("/{}-worker-0", prefix),
+                    epoch: 0,
+                    size: ReaderConnection::<38>::SIZE,
+                    queue: QueueState {
+                        start: 19,
+                        end: 31,
+                        done_reading: false,
+                        done_writing: true,
+                        pending_messages: 1,
+                        pending_bytes: 2,
+                    },
+                },
+                SegmentState {
+                    name: format!("/{}-worker-1", prefix),
+                    epoch: 1,
+                    size: ReaderConnection::<38>::SIZE,
+                    queue: QueueState {
+                        start: 0,
+                        end: 19,
+                        done_reading: false,
+                        done_writing: false,
+                        pending_messages: 1,
+                        pending_bytes: 9,
+                    },
+                },
+            ]
+        );
+        assert_eq!(
+            observer.pending().unwrap(),
+            vec![b"\xff\xfe".to_vec(), b"333333333".to_vec()]
+        );
+
+        // nothing has been consumed
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"\xff\xfe".to_vec()));
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"333333333".to_vec()));
+        assert!(observer.pending().unwrap().is_empty());
+
+        drop(reader);
+        assert_eq!(observer.state().unwrap().reader_pid, 0);
+    }
+
+    #[test]
+    fn test_follow() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let mut reader = Reader::<38>::new(&prefix).unwrap();
+        writer.ipc_push(b"111111111").unwrap();
+
+        let observer = Observer::attach(&prefix).unwrap();
+        let mut from_start = Position::default();
+        let mut from_end = observer.end().unwrap();
+        assert_eq!(
+            observer.read_from(&mut from_start).unwrap(),
+            vec![b"111111111".to_vec()]
+        );
+        assert!(observer.read_from(&mut from_end).unwrap().is_empty());
+
+        // popped messages are still seen, across queues
+        writer.ipc_push(b"22").unwrap();
+        writer.ipc_push(b"333333333").unwrap();
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"111111111".to_vec()));
+        assert_eq!(reader.ipc_pop().unwrap(), Some(b"22".to_vec()));
+        for position in [&mut from_start, &mut from_end] {
+            assert_eq!(
+                observer.read_from(position).unwrap(),
+                vec![b"22".to_vec(), b"333333333".to_vec()]
+            );
+        }
+        assert_eq!(from_start, from_end);
+        assert!(observer.read_from(&mut from_start).unwrap().is_empty());
+
+        assert!(!observer.is_detached().unwrap());
+        drop(reader);
+        writer.shutdown().unwrap();
+        assert!(observer.is_detached().unwrap());
+    }
+
+    #[test]
+    fn test_fragmented() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        let mut reader = Reader::<38>::new(&prefix).unwrap();
+        let observer = Observer::attach(&prefix).unwrap();
+        let mut position = Position::default();
+
+        // spread across three segments
+        let large: Vec<u8> = (0..60).collect();
+        writer.ipc_push(&large).unwrap();
+        writer.ipc_push(b"2").unwrap();
+
+        let state = observer.state().unwrap();
+        assert_eq!(state.stats.depth_messages, 2);
+        let pending: Vec<_> = state
+            .segments
+            .iter()
+            .map(|segment| segment.queue.pending_messages)
+            .collect();
+        assert_eq!(pending, vec![1, 0, 1]);
+        assert_eq!(
+            state
+                .segments
+                .iter()
+                .map(|segment| segment.queue.pending_bytes as u64)
+                .sum::<u64>(),
+            state.stats.depth_bytes
+        );
+
+        let expected = vec![large.clone(), b"2".to_vec()];
+        assert_eq!(observer.pending().unwrap(), expected);
+        assert_eq!(observer.read_from(&mut position).unwrap(), expected);
+
+        assert_eq!(reader.ipc_pop().unwrap(), Some(large));
+        assert_eq!(observer.pending().unwrap(), vec![b"2".to_vec()]);
+    }
+
+    #[test]
+    fn test_collector_unfinished() {
+        let frame = |kind, payload| Frame {
+            kind,
+            sequence: 7,
+            timestamp: None,
+            payload,
+        };
+        let mut first = 5u64.to_le_bytes().to_vec();
+        first.extend_from_slice(b"12");
+
+        let mut collector = Collector::default();
+        collector.collect(
+            3,
+            10,
+            vec![
+                (frame(Kind::Whole, b"0"), 20),
+                (frame(Kind::First, &first), 40),
+                (frame(Kind::Middle, b"3"), 50),
+            ],
+        );
+        // the rest hasn't been pushed yet
+        assert_eq!(collector.messages, vec![b"0".to_vec()]);
+        assert_eq!(
+            collector.unfinished,
+            Some(Position {
+                epoch: 3,
+                offset: 20
+            })
+        );
+
+        collector.collect(4, 0, vec![(frame(Kind::Last, b"45"), 10)]);
+        assert_eq!(collector.messages, vec![b"0".to_vec(), b"12345".to_vec()]);
+        assert_eq!(collector.unfinished, None);
+    }
+
+    #[test]
+    fn test_observer_without_writer() {
+        let prefix = crate::random_name();
+
+        assert!(Observer::attach(&prefix).is_err());
+    }
+}
@@ -0,0 +1,19 @@
+use crate::SegmentOptions;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReaderOptions {
+    // Fragmented messages longer than this are dropped (and reported)
+    // instead of being put back together in memory
+    pub max_message_size: usize,
+    // how worker segments are mapped
+    pub segments: SegmentOptions,
+}
+
+impl Default for ReaderOptions {
+    fn default() -> Self {
+        Self {
+            max_message_size: 16 << 20,
+            segments: SegmentOptions::default(),
+        }
+    }
+}
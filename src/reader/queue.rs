@@ -1,18 +1,38 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::{
+    channel::ChannelHeader,
+    frame::{self, CachePadded, Frame, Kind, FRAGMENTED_LEN, PADDED},
+};
+
+#[repr(C)]
 pub(crate) struct Queue<const N: usize> {
-    start: usize,
-    end: usize,
-    pub(crate) done_reading: bool,
-    pub(crate) done_writing: bool,
+    pub(crate) generation: u64,
+    pub(crate) epoch: u64,
+    pub(crate) flags: u64,
+    pub(crate) channel: ChannelHeader,
+    start: AtomicUsize,
+    end: AtomicUsize,
+    done_reading: AtomicBool,
+    done_writing: AtomicBool,
+    consumer: CachePadded,
+    producer: CachePadded,
     data: [u8; N],
 }
 
 impl<const N: usize> std::fmt::Debug for Queue<N> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Queue")
+            .field("generation", &self.generation)
+            .field("epoch", &self.epoch)
+            .field("flags", &self.flags)
+            .field("channel", &self.channel)
             .field("start", &self.start)
             .field("end", &self.end)
             .field("done_reading", &self.done_reading)
             .field("done_writing", &self.done_writing)
+            .field("consumer", &self.consumer)
+            .field("producer", &self.producer)
             .field("data", &self.data)
             .field("messages", &self.messages())
             .finish()
@@ -20,51 +40,169 @@ impl<const N: usize> std::fmt::Debug for Queue<N> {
 }
 
 impl<const N: usize> Queue<N> {
+    pub(crate) const SIZE: usize = std::mem::size_of::<Self>();
+    // the header doesn't depend on N, so the data of a segment of any size
+    // can be found without knowing it
+    pub(crate) const DATA_OFFSET: usize = std::mem::offset_of!(Self, data);
+
     pub(crate) fn from_ptr(ptr: *mut std::ffi::c_void) -> &'static mut Self {
         let ptr = ptr as *mut Queue<N>;
         unsafe { ptr.as_mut() }.unwrap()
     }
 
-    fn message_at(&self, at: usize) -> Option<Vec<u8>> {
-        let length = *self.data.get(at)?;
-        if length == 0 {
+    // For read-only mappings, nothing may be written through it
+    pub(crate) fn from_ptr_ref<'a>(ptr: *const std::ffi::c_void) -> &'a Self {
+        let ptr = ptr as *const Queue<N>;
+        unsafe { ptr.as_ref() }.unwrap()
+    }
+
+    // see the writer's `Queue`
+    fn start(&self) -> &AtomicUsize {
+        if self.flags & PADDED != 0 {
+            &self.consumer.0
+        } else {
+            &self.start
+        }
+    }
+
+    fn end(&self) -> &AtomicUsize {
+        if self.flags & PADDED != 0 {
+            &self.producer.0
+        } else {
+            &self.end
+        }
+    }
+
+    fn frame_at(&self, at: usize) -> Option<(Frame<'_>, usize)> {
+        if at >= self.end().load(Ordering::Acquire) {
             return None;
         }
-        Some(self.data[at + 1..at + (length as usize) + 1].to_vec())
+        frame::decode(&self.data, at, self.flags)
     }
 
+    // Fragments of larger messages are listed one by one,
+    // payloads that are in a segment of their own are left out
     pub(crate) fn messages(&self) -> Vec<String> {
+        let end = self.end().load(Ordering::Acquire);
+        frames(&self.data, self.flags, 0, end)
+            .into_iter()
+            .filter_map(|(frame, _)| match frame.kind {
+                Kind::First => Some(frame.payload.get(FRAGMENTED_LEN..).unwrap_or_default()),
+                Kind::Blob => None,
+                _ => Some(frame.payload),
+            })
+            .map(|message| String::from_utf8_lossy(message).into_owned())
+            .collect()
+    }
+
+    // Same as `pending`, but for the wrapping root queue
+    pub(crate) fn pending_wrapping(&self) -> Vec<&[u8]> {
         let mut messages = vec![];
-        let mut i = 0;
-        while i < N {
-            if let Some(message) = self.message_at(i) {
-                i += message.len() + 1;
-                messages.push(String::from_utf8(message).unwrap());
-            } else {
-                break;
+        let mut start = self.start().load(Ordering::Acquire);
+        let end = self.end().load(Ordering::Acquire);
+        while start < end {
+            if self.data[start % N] == 0 {
+                start += N - start % N;
+                continue;
             }
+            let at = start % N;
+            let length = self.data[at] as usize;
+            match self.data.get(at + 1..at + length + 1) {
+                Some(message) => messages.push(message),
+                None => break,
+            }
+            start += length + 1;
         }
         messages
     }
 
-    pub(crate) fn can_pop(&self) -> bool {
-        match self.data.get(self.start) {
-            None | Some(0) => false,
-            _ => true,
+    pub(crate) fn cursors(&self) -> (usize, usize) {
+        // `start` goes first, so it's never ahead of `end`
+        let start = self.start().load(Ordering::Acquire);
+        let end = self.end().load(Ordering::Acquire);
+        (start, end)
+    }
+
+    pub(crate) fn is_done_reading(&self) -> bool {
+        self.done_reading.load(Ordering::Acquire)
+    }
+
+    // Must be checked before popping, otherwise the last message
+    // could be pushed right before the writer sets the flag
+    pub(crate) fn is_done_writing(&self) -> bool {
+        self.done_writing.load(Ordering::Acquire)
+    }
+
+    // Hands the segment back to the writer, which is free to reuse it
+    // right away, so the queue must not be touched after that
+    pub(crate) fn mark_done_reading(&self) {
+        self.done_reading.store(true, Ordering::Release);
+    }
+
+    // Counterpart of the writer's `push_wrapping`, used for the root queue
+    pub(crate) fn pop_wrapping(&mut self) -> Option<Vec<u8>> {
+        let mut start = self.start().load(Ordering::Relaxed);
+        if start == self.end().load(Ordering::Acquire) {
+            return None;
         }
+
+        if self.data[start % N] == 0 {
+            start += N - start % N;
+        }
+
+        let at = start % N;
+        let length = self.data[at] as usize;
+        let message = self.data[at + 1..at + length + 1].to_vec();
+        self.start().store(start + length + 1, Ordering::Release);
+        Some(message)
     }
 
-    pub(crate) fn pop(&mut self) -> Option<Vec<u8>> {
-        // println!("[Reader] queue = {:?}", self);
-        if let Some(message) = self.message_at(self.start) {
-            self.start += message.len() + 1;
-            Some(message)
-        } else {
-            if self.done_writing {
-                self.done_reading = true;
+    // The next message and where the one after it starts,
+    // it stays in the queue until `advance` is called with that offset
+    pub(crate) fn peek(&self) -> Option<(Frame<'_>, usize)> {
+        self.frame_at(self.start().load(Ordering::Relaxed))
+    }
+
+    // Hands up to `max` messages to `f` and pops them all at once
+    pub(crate) fn drain(&self, max: usize, mut f: impl FnMut(Frame<'_>)) {
+        let (start, end) = self.cursors();
+        let mut i = start;
+        for _ in 0..max {
+            if i >= end {
+                break;
+            }
+            // fragments are left for the caller to reassemble
+            match frame::decode(&self.data, i, self.flags) {
+                Some((frame, next)) if frame.kind == Kind::Whole => {
+                    f(frame);
+                    i = next;
+                }
+                _ => break,
             }
+        }
+        self.advance(i);
+    }
 
-            return None;
+    pub(crate) fn advance(&self, next: usize) {
+        self.start().store(next, Ordering::Release);
+    }
+}
+
+// Frames in `data[from..end]` and where the next one starts, `data` being
+// the data area of a segment that may be mapped without knowing its queue size.
+// Out of bounds frames can only be seen by an observer racing
+// with the writer resetting the segment, they end the queue
+pub(crate) fn frames(data: &[u8], flags: u64, from: usize, end: usize) -> Vec<(Frame<'_>, usize)> {
+    let mut frames = vec![];
+    let mut i = from;
+    while i < end {
+        match frame::decode(data, i, flags) {
+            Some((frame, next)) => {
+                frames.push((frame, next));
+                i = next;
+            }
+            None => break,
         }
     }
+    frames
 }
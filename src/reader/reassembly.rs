@@ -0,0 +1,224 @@
+use crate::frame::{Frame, Kind, FRAGMENTED_LEN};
+
+// Puts fragmented messages back together, see `frame::Kind`.
+// The buffer is kept from one message to the next, so it's only
+// reallocated when a message is longer than any before it
+#[derive(Debug, Default)]
+pub(crate) struct Reassembly {
+    buffer: Vec<u8>,
+    current: Option<Current>,
+}
+
+#[derive(Debug)]
+struct Current {
+    sequence: u64,
+    timestamp: Option<u64>,
+    len: usize,
+    // too large, its fragments are skipped
+    dropped: bool,
+}
+
+pub(crate) enum Step {
+    Pending,
+    Complete {
+        sequence: u64,
+        timestamp: Option<u64>,
+    },
+    TooLarge {
+        sequence: u64,
+        len: usize,
+    },
+}
+
+impl Reassembly {
+    // Unfinished messages are dropped as soon as a fragment of another
+    // one shows up, as are fragments of messages that aren't being
+    // reassembled. Both only happen if the writer fails half way through
+    pub(crate) fn push(&mut self, frame: &Frame<'_>, max_message_size: usize) -> Step {
+        if frame.kind == Kind::First {
+            self.abandon();
+
+            let (len, payload) = match frame.payload.split_first_chunk::<FRAGMENTED_LEN>() {
+                Some((len, payload)) => (u64::from_le_bytes(*len) as usize, payload),
+                None => return Step::Pending,
+            };
+            let dropped = len > max_message_size;
+            self.current = Some(Current {
+                sequence: frame.sequence,
+                timestamp: frame.timestamp,
+                len,
+                dropped,
+            });
+            if dropped {
+                return Step::TooLarge {
+                    sequence: frame.sequence,
+                    len,
+                };
+            }
+            self.buffer.clear();
+            self.buffer.reserve(len);
+            self.buffer.extend_from_slice(payload);
+            return Step::Pending;
+        }
+
+        let current = match &self.current {
+            Some(current) if current.sequence == frame.sequence => current,
+            // left over from a message that's been given up on already
+            Some(current) if current.sequence > frame.sequence => return Step::Pending,
+            _ => {
+                self.abandon();
+                return Step::Pending;
+            }
+        };
+        if current.dropped {
+            if frame.kind == Kind::Last {
+                self.current = None;
+            }
+            return Step::Pending;
+        }
+        if self.buffer.len() + frame.payload.len() > current.len {
+            self.abandon();
+            return Step::Pending;
+        }
+
+        self.buffer.extend_from_slice(frame.payload);
+        if frame.kind != Kind::Last {
+            return Step::Pending;
+        }
+        let step = if self.buffer.len() == current.len {
+            Step::Complete {
+                sequence: current.sequence,
+                timestamp: current.timestamp,
+            }
+        } else {
+            Step::Pending
+        };
+        self.current = None;
+        step
+    }
+
+    // Whatever has been reassembled last
+    pub(crate) fn message(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub(crate) fn abandon(&mut self) {
+        if let Some(current) = self.current.take() {
+            if !current.dropped {
+                crate::event::event!(
+                    warn,
+                    "reader.fragments_dropped",
+                    sequence = current.sequence,
+                    received = self.buffer.len(),
+                    len = current.len,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(kind: Kind, sequence: u64, payload: &[u8]) -> Frame<'_> {
+        Frame {
+            kind,
+            sequence,
+            timestamp: None,
+            payload,
+        }
+    }
+
+    fn first(len: u64, payload: &[u8]) -> Vec<u8> {
+        let mut first = len.to_le_bytes().to_vec();
+        first.extend_from_slice(payload);
+        first
+    }
+
+    #[test]
+    fn test_reassembly() {
+        let mut reassembly = Reassembly::default();
+
+        let payload = first(6, b"12");
+        assert!(matches!(
+            reassembly.push(&frame(Kind::First, 0, &payload), 100),
+            Step::Pending
+        ));
+        assert!(matches!(
+            reassembly.push(&frame(Kind::Middle, 0, b"34"), 100),
+            Step::Pending
+        ));
+        assert!(matches!(
+            reassembly.push(&frame(Kind::Last, 0, b"56"), 100),
+            Step::Complete {
+                sequence: 0,
+                timestamp: None
+            }
+        ));
+        assert_eq!(reassembly.message(), b"123456");
+    }
+
+    #[test]
+    fn test_abandoned() {
+        let mut reassembly = Reassembly::default();
+
+        // the writer gave up half way through message 0
+        let payload = first(6, b"12");
+        reassembly.push(&frame(Kind::First, 0, &payload), 100);
+        let payload = first(3, b"ab");
+        reassembly.push(&frame(Kind::First, 1, &payload), 100);
+        // a stray fragment of message 0
+        reassembly.push(&frame(Kind::Last, 0, b"3456"), 100);
+        assert!(matches!(
+            reassembly.push(&frame(Kind::Last, 1, b"c"), 100),
+            Step::Complete { sequence: 1, .. }
+        ));
+        assert_eq!(reassembly.message(), b"abc");
+
+        // the first fragment of message 2 is missing
+        reassembly.push(&frame(Kind::Middle, 2, b"12"), 100);
+        assert!(matches!(
+            reassembly.push(&frame(Kind::Last, 2, b"3"), 100),
+            Step::Pending
+        ));
+
+        // longer than announced
+        let payload = first(3, b"ab");
+        reassembly.push(&frame(Kind::First, 3, &payload), 100);
+        assert!(matches!(
+            reassembly.push(&frame(Kind::Last, 3, b"cd"), 100),
+            Step::Pending
+        ));
+    }
+
+    #[test]
+    fn test_too_large() {
+        let mut reassembly = Reassembly::default();
+
+        let payload = first(6, b"12");
+        assert!(matches!(
+            reassembly.push(&frame(Kind::First, 0, &payload), 5),
+            Step::TooLarge {
+                sequence: 0,
+                len: 6
+            }
+        ));
+        assert!(matches!(
+            reassembly.push(&frame(Kind::Middle, 0, b"34"), 5),
+            Step::Pending
+        ));
+        assert!(matches!(
+            reassembly.push(&frame(Kind::Last, 0, b"56"), 5),
+            Step::Pending
+        ));
+
+        let payload = first(3, b"ab");
+        reassembly.push(&frame(Kind::First, 1, &payload), 5);
+        assert!(matches!(
+            reassembly.push(&frame(Kind::Last, 1, b"c"), 5),
+            Step::Complete { sequence: 1, .. }
+        ));
+        assert_eq!(reassembly.message(), b"abc");
+    }
+}
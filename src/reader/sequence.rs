@@ -0,0 +1,120 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceGap {
+    // the writer generation the gap is in
+    pub generation: u64,
+    pub expected: u64,
+    // sequence number of the message popped instead,
+    // or how many messages the writer had pushed before it was restarted
+    pub found: u64,
+}
+
+impl SequenceGap {
+    pub fn missed(&self) -> u64 {
+        self.found - self.expected
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SequenceReport {
+    pub gaps: u64,
+    pub missed: u64,
+    pub duplicates: u64,
+    pub last_gap: Option<SequenceGap>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SequenceTracker {
+    expected: u64,
+    report: SequenceReport,
+}
+
+pub(crate) enum Check {
+    InOrder,
+    Gap(SequenceGap),
+    Duplicate,
+}
+
+impl SequenceTracker {
+    pub(crate) fn check(&mut self, generation: u64, sequence: u64) -> Check {
+        if sequence < self.expected {
+            self.report.duplicates += 1;
+            return Check::Duplicate;
+        }
+
+        let expected = self.expected;
+        self.expected = sequence + 1;
+        if sequence == expected {
+            return Check::InOrder;
+        }
+        let gap = self.gap(generation, expected, sequence);
+        Check::Gap(gap)
+    }
+
+    // The previous writer pushed `pushed` messages in total,
+    // whatever the reader hasn't got to is gone with it
+    pub(crate) fn restarted(&mut self, generation: u64, pushed: u64) -> Option<SequenceGap> {
+        let expected = std::mem::replace(&mut self.expected, 0);
+        if pushed > expected {
+            Some(self.gap(generation, expected, pushed))
+        } else {
+            None
+        }
+    }
+
+    fn gap(&mut self, generation: u64, expected: u64, found: u64) -> SequenceGap {
+        let gap = SequenceGap {
+            generation,
+            expected,
+            found,
+        };
+        self.report.gaps += 1;
+        self.report.missed += gap.missed();
+        self.report.last_gap = Some(gap);
+        gap
+    }
+
+    pub(crate) fn report(&self) -> SequenceReport {
+        self.report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracker() {
+        let mut tracker = SequenceTracker::default();
+
+        assert!(matches!(tracker.check(1, 0), Check::InOrder));
+        assert!(matches!(tracker.check(1, 1), Check::InOrder));
+        assert!(matches!(tracker.check(1, 1), Check::Duplicate));
+        assert!(matches!(tracker.check(1, 5), Check::Gap(gap) if gap.missed() == 3));
+        assert!(matches!(tracker.check(1, 6), Check::InOrder));
+
+        assert_eq!(tracker.restarted(1, 7), None);
+        assert!(matches!(tracker.check(2, 0), Check::InOrder));
+        assert_eq!(
+            tracker.restarted(2, 10),
+            Some(SequenceGap {
+                generation: 2,
+                expected: 1,
+                found: 10,
+            })
+        );
+
+        assert_eq!(
+            tracker.report(),
+            SequenceReport {
+                gaps: 2,
+                missed: 12,
+                duplicates: 1,
+                last_gap: Some(SequenceGap {
+                    generation: 2,
+                    expected: 1,
+                    found: 10,
+                }),
+            }
+        );
+    }
+}
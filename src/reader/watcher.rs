@@ -0,0 +1,71 @@
+use std::time::{Duration, Instant};
+
+use libc::{IN_CLOEXEC, IN_CREATE, IN_MODIFY, IN_MOVED_TO, IN_NONBLOCK};
+
+use crate::{
+    capi::{close, drain, inotify_add_watch, inotify_init1, poll_readable},
+    reader::ReaderError,
+};
+
+// Writes into an already mapped segment don't produce inotify events,
+// so whatever is announced through shared memory is re-checked this often
+pub(crate) const RECHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+pub(crate) struct ShmWatcher {
+    fd: Option<i32>,
+}
+
+impl ShmWatcher {
+    pub(crate) fn new() -> Self {
+        let fd =
+            inotify_init1(IN_NONBLOCK | IN_CLOEXEC).ok().and_then(|fd| {
+                match inotify_add_watch(fd, c"/dev/shm", IN_CREATE | IN_MODIFY | IN_MOVED_TO) {
+                    Ok(_) => Some(fd),
+                    Err(_) => {
+                        let _ = close(fd);
+                        None
+                    }
+                }
+            });
+
+        Self { fd }
+    }
+
+    // Blocks until something changes in /dev/shm, `max_wait` elapses
+    // or `deadline` is reached (in which case it returns `Timeout`)
+    pub(crate) fn wait(
+        &self,
+        deadline: Instant,
+        max_wait: Option<Duration>,
+    ) -> Result<(), ReaderError> {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(ReaderError::Timeout);
+        }
+        let mut timeout = deadline - now;
+
+        match self.fd {
+            Some(fd) => {
+                if let Some(max_wait) = max_wait {
+                    timeout = timeout.min(max_wait);
+                }
+                let timeout_ms = timeout.as_millis().clamp(1, i32::MAX as u128) as i32;
+                // EINTR is just a spurious wakeup, the caller re-checks anyway
+                if let Ok(true) = poll_readable(fd, timeout_ms) {
+                    drain(fd);
+                }
+            }
+            None => std::thread::sleep(timeout.min(RECHECK_INTERVAL)),
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for ShmWatcher {
+    fn drop(&mut self) {
+        if let Some(fd) = self.fd {
+            let _ = close(fd);
+        }
+    }
+}
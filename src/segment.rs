@@ -0,0 +1,107 @@
+use std::{ffi::c_void, io};
+
+use crate::capi::{madvise, mlock, page_size};
+
+// How worker segments are mapped, by the writer (see `WriterOptions`)
+// and the reader (see `ReaderOptions`) alike. Everything is done once
+// per mapping, recycled segments stay as they are
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SegmentOptions {
+    pub prefault: Prefault,
+    // mlock, so that the segment is never swapped out.
+    // Limited by RLIMIT_MEMLOCK unless the process has CAP_IPC_LOCK
+    pub lock: bool,
+    pub advice: Option<Advice>,
+}
+
+// Without it every page takes a fault the first time it's touched,
+// which shows up as latency right after every rotation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Prefault {
+    #[default]
+    None,
+    // MAP_POPULATE, the kernel faults everything in while mapping
+    Populate,
+    // Every page is touched right after mapping, by a write on the writer's
+    // side (the segment is brand new) and a read on the reader's
+    Touch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advice {
+    Sequential,
+    WillNeed,
+    // Transparent huge pages, only if shmem_enabled allows it
+    HugePage,
+}
+
+impl Advice {
+    fn as_raw(self) -> std::ffi::c_int {
+        match self {
+            Self::Sequential => libc::MADV_SEQUENTIAL,
+            Self::WillNeed => libc::MADV_WILLNEED,
+            Self::HugePage => libc::MADV_HUGEPAGE,
+        }
+    }
+}
+
+pub(crate) enum Failure {
+    Madvise(io::Error),
+    Mlock(io::Error),
+}
+
+impl SegmentOptions {
+    pub(crate) fn mmap_flags(&self) -> std::ffi::c_int {
+        match self.prefault {
+            Prefault::Populate => libc::MAP_POPULATE,
+            _ => 0,
+        }
+    }
+
+    // Whatever isn't done by mmap itself, see `mmap_flags`
+    pub(crate) fn apply(
+        &self,
+        addr: *mut c_void,
+        size: usize,
+        writable: bool,
+    ) -> Result<(), Failure> {
+        if let Some(advice) = self.advice {
+            madvise(addr, size, advice.as_raw()).map_err(Failure::Madvise)?;
+        }
+        if self.lock {
+            mlock(addr, size).map_err(Failure::Mlock)?;
+        }
+        if self.prefault == Prefault::Touch {
+            let addr = addr.cast::<u8>();
+            for offset in (0..size).step_by(page_size()) {
+                unsafe {
+                    if writable {
+                        addr.add(offset).write_volatile(0);
+                    } else {
+                        addr.add(offset).read_volatile();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// mlock is refused past RLIMIT_MEMLOCK (64 KiB by default, summed over
+// every test running in the process) or without the right to lock at all,
+// which says nothing about the code under test
+#[cfg(test)]
+pub(crate) fn mlock_refused(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut err = Some(err);
+    while let Some(current) = err {
+        if current.to_string().starts_with("mlock failed") {
+            return current
+                .source()
+                .and_then(|source| source.downcast_ref::<io::Error>())
+                .and_then(io::Error::raw_os_error)
+                .is_some_and(|code| code == libc::ENOMEM || code == libc::EPERM);
+        }
+        err = current.source();
+    }
+    false
+}
@@ -0,0 +1,305 @@
+use std::{io, os::unix::fs::MetadataExt};
+
+use crate::{
+    capi::{shm_unlink, statvfs},
+    ConnectionType,
+};
+
+const SHM_DIR: &str = "/dev/shm";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentInfo {
+    pub name: String,
+    pub size: u64,
+    pub uid: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelInfo {
+    pub prefix: String,
+    // None if only worker segments are left behind
+    pub root: Option<SegmentInfo>,
+    // sorted by worker number
+    pub workers: Vec<(usize, SegmentInfo)>,
+    // see `Slab`
+    pub slab: Option<SegmentInfo>,
+    // see `PodWriter`
+    pub pod: Option<SegmentInfo>,
+    // payloads pushed with `Writer::push_large` that aren't picked up yet,
+    // sorted by name
+    pub blobs: Vec<SegmentInfo>,
+}
+
+enum Segment<'a> {
+    Root(&'a str),
+    Worker(&'a str, usize),
+    Slab(&'a str),
+    Pod(&'a str),
+    Blob(&'a str),
+}
+
+// Inverse of `ConnectionType::root`, `ConnectionType::worker`, `ConnectionType::slab`,
+// `ConnectionType::pod` and `ConnectionType::blob`, the prefix itself may contain dashes
+fn parse(file_name: &str) -> Option<Segment<'_>> {
+    if let Some(prefix) = file_name.strip_suffix("-root") {
+        return Some(Segment::Root(prefix));
+    }
+    if let Some(prefix) = file_name.strip_suffix("-slab") {
+        return Some(Segment::Slab(prefix));
+    }
+    if let Some(prefix) = file_name.strip_suffix("-pod") {
+        return Some(Segment::Pod(prefix));
+    }
+    if let Some((prefix, n)) = file_name.rsplit_once("-worker-") {
+        return Some(Segment::Worker(prefix, n.parse().ok()?));
+    }
+    let (prefix, id) = file_name.rsplit_once("-blob-")?;
+    let (generation, sequence) = id.split_once('-')?;
+    generation.parse::<u64>().ok()?;
+    sequence.parse::<u64>().ok()?;
+    Some(Segment::Blob(prefix))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShmSpace {
+    pub total: u64,
+    // what's left for new segments
+    pub free: u64,
+}
+
+// Worth checking before provisioning large (or many) segments,
+// running out of it half way is a `WriterConnectError::OutOfSpace`
+pub fn shm_space() -> io::Result<ShmSpace> {
+    let stat = statvfs(&std::ffi::CString::new(SHM_DIR).unwrap())?;
+    Ok(ShmSpace {
+        total: stat.f_blocks * stat.f_frsize,
+        free: stat.f_bavail * stat.f_frsize,
+    })
+}
+
+// Every channel that has at least one segment in /dev/shm, sorted by prefix
+pub fn list_channels() -> io::Result<Vec<ChannelInfo>> {
+    let mut channels: Vec<ChannelInfo> = vec![];
+
+    for entry in std::fs::read_dir(SHM_DIR)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let file_name = match file_name.to_str() {
+            Some(file_name) => file_name,
+            None => continue,
+        };
+        let segment = match parse(file_name) {
+            Some(segment) => segment,
+            None => continue,
+        };
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            // unlinked while we were looking
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        let info = SegmentInfo {
+            name: format!("/{}", file_name),
+            size: metadata.size(),
+            uid: metadata.uid(),
+        };
+
+        let prefix = match segment {
+            Segment::Root(prefix)
+            | Segment::Worker(prefix, _)
+            | Segment::Slab(prefix)
+            | Segment::Pod(prefix)
+            | Segment::Blob(prefix) => prefix,
+        };
+        let channel = match channels.iter().position(|channel| channel.prefix == prefix) {
+            Some(i) => &mut channels[i],
+            None => {
+                channels.push(ChannelInfo {
+                    prefix: prefix.to_string(),
+                    root: None,
+                    workers: vec![],
+                    slab: None,
+                    pod: None,
+                    blobs: vec![],
+                });
+                channels.last_mut().unwrap()
+            }
+        };
+        match segment {
+            Segment::Root(_) => channel.root = Some(info),
+            Segment::Worker(_, n) => channel.workers.push((n, info)),
+            Segment::Slab(_) => channel.slab = Some(info),
+            Segment::Pod(_) => channel.pod = Some(info),
+            Segment::Blob(_) => channel.blobs.push(info),
+        }
+    }
+
+    channels.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+    for channel in &mut channels {
+        channel.workers.sort_by_key(|(n, _)| *n);
+        channel.blobs.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+    Ok(channels)
+}
+
+// Worker numbers of the segments of a channel, in no particular order
+pub(crate) fn worker_numbers(prefix: &str) -> io::Result<Vec<usize>> {
+    let mut numbers = vec![];
+    for entry in std::fs::read_dir(SHM_DIR)? {
+        let file_name = entry?.file_name();
+        if let Some(Segment::Worker(worker_prefix, n)) = file_name.to_str().and_then(parse) {
+            if worker_prefix == prefix {
+                numbers.push(n);
+            }
+        }
+    }
+    Ok(numbers)
+}
+
+// Names of the payload segments of a channel, in no particular order
+fn blob_names(prefix: &str) -> io::Result<Vec<String>> {
+    let mut names = vec![];
+    for entry in std::fs::read_dir(SHM_DIR)? {
+        let file_name = entry?.file_name();
+        let file_name = match file_name.to_str() {
+            Some(file_name) => file_name,
+            None => continue,
+        };
+        if let Some(Segment::Blob(blob_prefix)) = parse(file_name) {
+            if blob_prefix == prefix {
+                names.push(format!("/{}", file_name));
+            }
+        }
+    }
+    Ok(names)
+}
+
+// Unlinks every segment of a channel, root first so that a reader
+// sees it as gone before its queues start disappearing.
+// Whoever has them mapped keeps them until they unmap.
+// Returns the names of the unlinked segments
+pub fn purge_channel(prefix: &str) -> io::Result<Vec<String>> {
+    let mut segments = vec![ConnectionType::root(prefix)];
+    let mut numbers = worker_numbers(prefix)?;
+    numbers.sort_unstable();
+    segments.extend(
+        numbers
+            .into_iter()
+            .map(|n| ConnectionType::worker(n, prefix)),
+    );
+    segments.push(ConnectionType::slab(prefix));
+    segments.push(ConnectionType::pod(prefix));
+    let mut blobs = blob_names(prefix)?;
+    blobs.sort_unstable();
+    segments.extend(
+        blobs
+            .iter()
+            .map(|name| ConnectionType::exact(name.as_bytes())),
+    );
+
+    let mut purged = vec![];
+    for segment in segments {
+        match shm_unlink(segment.id()) {
+            Ok(()) => purged.push(segment.name()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PodWriter, Slab, Writer};
+
+    #[test]
+    fn test_list_and_purge() {
+        let prefix = format!("{}-with-dashes", crate::random_name());
+
+        let mut writer = Writer::<38>::new(&prefix).unwrap();
+        writer.ipc_push(b"111111111").unwrap();
+        writer.ipc_push(b"222222222").unwrap();
+        writer.ipc_push(b"333333333").unwrap();
+        writer.push_large(b"444444444").unwrap();
+        let _slab = Slab::create(&prefix, 10, 1).unwrap();
+        let _pod = PodWriter::<u64>::new(&prefix, 4).unwrap();
+
+        let channels = list_channels().unwrap();
+        let channel = channels
+            .iter()
+            .find(|channel| channel.prefix == prefix)
+            .unwrap();
+        let uid = unsafe { libc::getuid() };
+        assert_eq!(
+            channel
+                .root
+                .as_ref()
+                .map(|root| (root.name.clone(), root.uid)),
+            Some((format!("/{}-root", prefix), uid))
+        );
+        assert_eq!(
+            channel.workers.iter().map(|(n, _)| *n).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        assert!(channel.workers.iter().all(|(_, info)| info.size > 20));
+        let blob = format!("/{}-blob-{}-3", prefix, writer.generation());
+        assert_eq!(
+            channel
+                .blobs
+                .iter()
+                .map(|info| (info.name.clone(), info.size))
+                .collect::<Vec<_>>(),
+            vec![(blob.clone(), 9)]
+        );
+        assert_eq!(
+            channel.slab.as_ref().map(|slab| slab.name.clone()),
+            Some(format!("/{}-slab", prefix))
+        );
+        assert_eq!(
+            channel.pod.as_ref().map(|pod| pod.name.clone()),
+            Some(format!("/{}-pod", prefix))
+        );
+
+        assert_eq!(
+            purge_channel(&prefix).unwrap(),
+            vec![
+                format!("/{}-root", prefix),
+                format!("/{}-worker-0", prefix),
+                format!("/{}-worker-1", prefix),
+                format!("/{}-worker-2", prefix),
+                format!("/{}-slab", prefix),
+                format!("/{}-pod", prefix),
+                blob,
+            ]
+        );
+        assert!(list_channels()
+            .unwrap()
+            .iter()
+            .all(|channel| channel.prefix != prefix));
+
+        // the writer finds its segments already gone
+        assert!(writer.shutdown().is_err());
+    }
+
+    #[test]
+    fn test_out_of_space() {
+        let space = shm_space().unwrap();
+        assert!(0 < space.free && space.free <= space.total);
+
+        // larger than any /dev/shm
+        let connection_type = ConnectionType::random();
+        let err = crate::WriterConnection::<{ 1 << 46 }>::new(connection_type.clone()).unwrap_err();
+        assert!(matches!(
+            err,
+            crate::WriterConnectError::OutOfSpace { size, .. } if size > 1 << 46
+        ));
+        assert_eq!(err.operation(), "posix_fallocate");
+        assert_eq!(
+            std::io::Error::from(err).kind(),
+            std::io::ErrorKind::StorageFull
+        );
+        // nothing is left behind
+        assert!(crate::capi::shm_unlink(connection_type.id()).is_err());
+    }
+}
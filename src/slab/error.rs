@@ -0,0 +1,80 @@
+use std::{error::Error, fmt, io};
+
+use crate::{ReaderConnectError, SlabHandle, WriterConnectError};
+
+#[derive(Debug)]
+pub enum SlabError {
+    CreateError(WriterConnectError),
+    OpenError(ReaderConnectError),
+    Full,
+    TooLarge { len: usize, max: usize },
+    // out of range, or not allocated (a double free, for one),
+    // or the slot has been handed out again since
+    InvalidHandle(SlabHandle),
+    // the process that has created it is still running
+    InUse { segment: String, pid: u32 },
+}
+
+impl fmt::Display for SlabError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CreateError(_) => f.write_str("failed to create the slab segment"),
+            Self::OpenError(_) => f.write_str("failed to open the slab segment"),
+            Self::Full => f.write_str("no free slot in the slab"),
+            Self::TooLarge { len, max } => {
+                write!(
+                    f,
+                    "buffer of {} bytes is larger than a slot of {} bytes",
+                    len, max
+                )
+            }
+            Self::InvalidHandle(handle) => write!(
+                f,
+                "no buffer of {} bytes is allocated at offset {} (generation {})",
+                handle.len, handle.offset, handle.generation
+            ),
+            Self::InUse { segment, pid } => {
+                write!(f, "slab segment {:?} is in use by pid {}", segment, pid)
+            }
+        }
+    }
+}
+
+impl Error for SlabError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::CreateError(err) => Some(err),
+            Self::OpenError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<SlabError> for io::Error {
+    fn from(err: SlabError) -> Self {
+        let kind = match &err {
+            SlabError::CreateError(err) => err.io_error().kind(),
+            SlabError::OpenError(err) => match err.io_error() {
+                Some(source) => source.kind(),
+                None => io::ErrorKind::WouldBlock,
+            },
+            SlabError::Full => io::ErrorKind::WouldBlock,
+            SlabError::TooLarge { .. } => io::ErrorKind::InvalidInput,
+            SlabError::InvalidHandle(_) => io::ErrorKind::InvalidInput,
+            SlabError::InUse { .. } => io::ErrorKind::AddrInUse,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+impl From<WriterConnectError> for SlabError {
+    fn from(err: WriterConnectError) -> Self {
+        Self::CreateError(err)
+    }
+}
+
+impl From<ReaderConnectError> for SlabError {
+    fn from(err: ReaderConnectError) -> Self {
+        Self::OpenError(err)
+    }
+}
@@ -0,0 +1,34 @@
+// What goes through the queue instead of the buffer itself:
+//
+//   offset: u64 | len: u32 | generation: u32
+//
+// `offset` is where the buffer starts in the data area of the slab,
+// `generation` tells this allocation of the slot from later ones
+pub const SLAB_HANDLE_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlabHandle {
+    pub offset: u64,
+    pub len: u32,
+    pub generation: u32,
+}
+
+impl SlabHandle {
+    pub fn to_bytes(&self) -> [u8; SLAB_HANDLE_LEN] {
+        let mut bytes = [0; SLAB_HANDLE_LEN];
+        bytes[..8].copy_from_slice(&self.offset.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.len.to_le_bytes());
+        bytes[12..].copy_from_slice(&self.generation.to_le_bytes());
+        bytes
+    }
+
+    // None unless it's exactly `SLAB_HANDLE_LEN` bytes
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let bytes: &[u8; SLAB_HANDLE_LEN] = bytes.try_into().ok()?;
+        Some(Self {
+            offset: u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+            len: u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+            generation: u32::from_le_bytes(bytes[12..].try_into().unwrap()),
+        })
+    }
+}
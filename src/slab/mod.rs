@@ -0,0 +1,677 @@
+mod error;
+pub use error::SlabError;
+
+mod handle;
+pub use handle::{SlabHandle, SLAB_HANDLE_LEN};
+
+use std::{
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+};
+
+use libc::{
+    MAP_SHARED, O_CREAT, O_EXCL, O_RDONLY, O_RDWR, PROT_READ, PROT_WRITE, S_IRUSR, S_IWUSR,
+};
+
+use crate::{
+    capi::{close, fstat, mmap, munmap, process_exists, shm_open, shm_unlink},
+    event::event,
+    writer::allocate,
+    ConnectionType, ReaderConnectError, WriterConnectError,
+};
+
+// Buffers for payloads too large to be copied through the queues, shared
+// by every process that opens the slab: one of them allocates a buffer,
+// fills it and pushes its `SlabHandle`, whoever pops it reads the buffer
+// right where it is and frees it.
+//
+// The segment is a header, then a link and a state for every slot, then
+// the slots themselves, each of them starting on a cache line.
+// Free slots are kept in a lock-free stack, its head is tagged with
+// a counter so that a slot popped and pushed back in the meantime
+// can't be mistaken for the one that was seen.
+// The state of a slot is `generation << 1 | ALLOCATED` (or `FREE`), the
+// generation goes up with every allocation and is part of the handle,
+// so a handle that has been freed already can't free the slot's next owner
+#[repr(C)]
+struct Header {
+    // the largest buffer a slot can take
+    slot_size: u64,
+    slots: u64,
+    // tag << 32 | index + 1 of the first free slot, 0 if there is none
+    free_head: AtomicU64,
+    free_slots: AtomicU64,
+    // of the process that has created it
+    owner_pid: AtomicU32,
+    initialized: AtomicBool,
+}
+
+const FREE: u32 = 0;
+const ALLOCATED: u32 = 1;
+
+fn allocated(generation: u32) -> u32 {
+    generation << 1 | ALLOCATED
+}
+
+const CACHE_LINE: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Layout {
+    stride: usize,
+    links: usize,
+    states: usize,
+    data: usize,
+    size: usize,
+}
+
+impl Layout {
+    fn new(slot_size: usize, slots: usize) -> Self {
+        let links = std::mem::size_of::<Header>();
+        let states = links + slots * 4;
+        let data = (states + slots * 4).next_multiple_of(CACHE_LINE);
+        let stride = slot_size.max(1).next_multiple_of(CACHE_LINE);
+        Self {
+            stride,
+            links,
+            states,
+            data,
+            size: data + stride * slots,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Slab {
+    fd: i32,
+    addr: *mut std::ffi::c_void,
+    layout: Layout,
+    connection_type: ConnectionType,
+    // whoever has created it unlinks it when it goes away
+    owner: bool,
+}
+
+impl Slab {
+    pub fn create(prefix: &str, slot_size: usize, slots: u32) -> Result<Self, SlabError> {
+        // it has to fit in a handle
+        if slot_size > u32::MAX as usize {
+            return Err(SlabError::TooLarge {
+                len: slot_size,
+                max: u32::MAX as usize,
+            });
+        }
+        let connection_type = ConnectionType::slab(prefix);
+        let layout = Layout::new(slot_size, slots as usize);
+
+        // its consumers would be left with the old one
+        if let Some(pid) = live_owner(&connection_type) {
+            return Err(SlabError::InUse {
+                segment: connection_type.name(),
+                pid,
+            });
+        }
+        // same as worker segments, a stale one may still be mapped somewhere
+        let _ = shm_unlink(connection_type.id());
+
+        let fd = shm_open(
+            connection_type.id(),
+            O_RDWR | O_CREAT | O_EXCL,
+            (S_IRUSR | S_IWUSR) as std::ffi::c_uint,
+        )
+        .map_err(|source| WriterConnectError::ShmOpenError {
+            segment: connection_type.name(),
+            source,
+        })?;
+
+        let mut slab = Self {
+            fd,
+            addr: std::ptr::null_mut(),
+            layout,
+            connection_type,
+            owner: true,
+        };
+
+        allocate(fd, layout.size, &slab.connection_type)?;
+        slab.addr = mmap(
+            std::ptr::null_mut(),
+            layout.size,
+            PROT_WRITE,
+            MAP_SHARED,
+            fd,
+            0,
+        )
+        .map_err(|source| WriterConnectError::MmapError {
+            segment: slab.connection_type.name(),
+            source,
+        })?;
+
+        let header = Header {
+            slot_size: slot_size as u64,
+            slots: slots as u64,
+            free_head: AtomicU64::new(if slots > 0 { 1 } else { 0 }),
+            free_slots: AtomicU64::new(slots as u64),
+            owner_pid: AtomicU32::new(std::process::id()),
+            initialized: AtomicBool::new(false),
+        };
+        unsafe { std::ptr::write(slab.addr.cast::<Header>(), header) };
+        for (i, link) in slab.links().iter().enumerate() {
+            // the last one links to nothing
+            let next = if i + 1 < slots as usize { i + 2 } else { 0 };
+            link.store(next as u32, Ordering::Relaxed);
+        }
+        slab.header().initialized.store(true, Ordering::Release);
+
+        event!(
+            debug,
+            "slab.created",
+            segment = slab.connection_type.name(),
+            slot_size = slot_size,
+            slots = slots,
+            size = layout.size,
+        );
+
+        Ok(slab)
+    }
+
+    pub fn open(prefix: &str) -> Result<Self, SlabError> {
+        let connection_type = ConnectionType::slab(prefix);
+
+        let fd = shm_open(
+            connection_type.id(),
+            O_RDWR,
+            (S_IRUSR | S_IWUSR) as std::ffi::c_uint,
+        )
+        .map_err(|source| ReaderConnectError::ShmOpenError {
+            segment: connection_type.name(),
+            source,
+        })?;
+
+        let mut slab = Self {
+            fd,
+            addr: std::ptr::null_mut(),
+            layout: Layout::new(0, 0),
+            connection_type,
+            owner: false,
+        };
+
+        let uninitialized = || ReaderConnectError::Uninitialized {
+            segment: slab.connection_type.name(),
+        };
+        let stat = fstat(fd).map_err(|source| ReaderConnectError::FstatError {
+            segment: slab.connection_type.name(),
+            source,
+        })?;
+        let size = stat.st_size as usize;
+        if size < std::mem::size_of::<Header>() {
+            return Err(uninitialized().into());
+        }
+
+        slab.addr =
+            mmap(std::ptr::null_mut(), size, PROT_WRITE, MAP_SHARED, fd, 0).map_err(|source| {
+                ReaderConnectError::MmapError {
+                    segment: slab.connection_type.name(),
+                    source,
+                }
+            })?;
+        slab.layout.size = size;
+
+        let header = slab.header();
+        if !header.initialized.load(Ordering::Acquire) {
+            return Err(uninitialized().into());
+        }
+        let layout = Layout::new(header.slot_size as usize, header.slots as usize);
+        if layout.size != size {
+            return Err(uninitialized().into());
+        }
+        slab.layout = layout;
+
+        Ok(slab)
+    }
+
+    pub fn slot_size(&self) -> usize {
+        self.header().slot_size as usize
+    }
+
+    pub fn slots(&self) -> usize {
+        self.header().slots as usize
+    }
+
+    pub fn free_slots(&self) -> usize {
+        self.header().free_slots.load(Ordering::Relaxed) as usize
+    }
+
+    // The buffer goes back to the slab if it's dropped
+    // instead of being turned into a handle
+    pub fn alloc(&self, len: usize) -> Result<SlabBuffer<'_>, SlabError> {
+        let max = self.slot_size();
+        if len > max {
+            return Err(SlabError::TooLarge { len, max });
+        }
+
+        let header = self.header();
+        let mut head = header.free_head.load(Ordering::Acquire);
+        let index = loop {
+            let index = match (head & u32::MAX as u64) as usize {
+                0 => return Err(SlabError::Full),
+                index => index - 1,
+            };
+            // may be stale if somebody else takes it first, the tag tells
+            let next = self.links()[index].load(Ordering::Relaxed) as u64;
+            let tag = (head >> 32) + 1;
+            match header.free_head.compare_exchange_weak(
+                head,
+                tag << 32 | next,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break index,
+                Err(current) => head = current,
+            }
+        };
+
+        let state = &self.states()[index];
+        let generation = (state.load(Ordering::Relaxed) >> 1).wrapping_add(1) & (u32::MAX >> 1);
+        state.store(allocated(generation), Ordering::Relaxed);
+        header.free_slots.fetch_sub(1, Ordering::Relaxed);
+        Ok(SlabBuffer {
+            slab: self,
+            index,
+            len,
+            generation,
+        })
+    }
+
+    // The buffer behind a handle allocated by anyone who has the slab open
+    pub fn get(&self, handle: SlabHandle) -> Result<&[u8], SlabError> {
+        let index = self.slot(handle)?;
+        if self.states()[index].load(Ordering::Acquire) != allocated(handle.generation) {
+            return Err(SlabError::InvalidHandle(handle));
+        }
+        Ok(unsafe { std::slice::from_raw_parts(self.slot_ptr(index), handle.len as usize) })
+    }
+
+    // Hands the buffer back, whoever has allocated it
+    pub fn free(&self, handle: SlabHandle) -> Result<(), SlabError> {
+        let index = self.slot(handle)?;
+        self.states()[index]
+            .compare_exchange(
+                allocated(handle.generation),
+                handle.generation << 1 | FREE,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            )
+            .map_err(|_| SlabError::InvalidHandle(handle))?;
+        self.release(index);
+        Ok(())
+    }
+
+    fn release(&self, index: usize) {
+        let header = self.header();
+        header.free_slots.fetch_add(1, Ordering::Relaxed);
+        let mut head = header.free_head.load(Ordering::Relaxed);
+        loop {
+            self.links()[index].store((head & u32::MAX as u64) as u32, Ordering::Relaxed);
+            let tag = (head >> 32) + 1;
+            match header.free_head.compare_exchange_weak(
+                head,
+                tag << 32 | (index as u64 + 1),
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    // Index of the slot a handle points to, if it can be one at all
+    fn slot(&self, handle: SlabHandle) -> Result<usize, SlabError> {
+        let offset = handle.offset as usize;
+        let index = offset / self.layout.stride;
+        if !offset.is_multiple_of(self.layout.stride)
+            || index >= self.slots()
+            || handle.len as usize > self.slot_size()
+            || handle.generation > u32::MAX >> 1
+        {
+            return Err(SlabError::InvalidHandle(handle));
+        }
+        Ok(index)
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*self.addr.cast::<Header>() }
+    }
+
+    fn links(&self) -> &[AtomicU32] {
+        self.array(self.layout.links)
+    }
+
+    fn states(&self) -> &[AtomicU32] {
+        self.array(self.layout.states)
+    }
+
+    fn array(&self, offset: usize) -> &[AtomicU32] {
+        unsafe {
+            let ptr = self.addr.cast::<u8>().add(offset).cast::<AtomicU32>();
+            std::slice::from_raw_parts(ptr, self.slots())
+        }
+    }
+
+    fn slot_ptr(&self, index: usize) -> *mut u8 {
+        let offset = self.layout.data + index * self.layout.stride;
+        unsafe { self.addr.cast::<u8>().add(offset) }
+    }
+}
+
+impl Drop for Slab {
+    fn drop(&mut self) {
+        if !self.addr.is_null() {
+            let _ = munmap(self.addr, self.layout.size);
+        }
+        let _ = close(self.fd);
+        // whoever still has it mapped keeps it until they unmap
+        if self.owner {
+            let _ = shm_unlink(self.connection_type.id());
+        }
+    }
+}
+
+// Pid of the process that has created the slab of that name,
+// if that one is still running
+fn live_owner(connection_type: &ConnectionType) -> Option<u32> {
+    let fd = shm_open(
+        connection_type.id(),
+        O_RDONLY,
+        (S_IRUSR | S_IWUSR) as std::ffi::c_uint,
+    )
+    .ok()?;
+    let mut slab = Slab {
+        fd,
+        addr: std::ptr::null_mut(),
+        layout: Layout::new(0, 0),
+        connection_type: connection_type.clone(),
+        owner: false,
+    };
+    slab.layout.size = std::mem::size_of::<Header>();
+    if (fstat(fd).ok()?.st_size as usize) < slab.layout.size {
+        return None;
+    }
+    slab.addr = mmap(
+        std::ptr::null_mut(),
+        slab.layout.size,
+        PROT_READ,
+        MAP_SHARED,
+        fd,
+        0,
+    )
+    .ok()?;
+
+    let header = slab.header();
+    if !header.initialized.load(Ordering::Acquire) {
+        return None;
+    }
+    let pid = header.owner_pid.load(Ordering::Relaxed);
+    process_exists(pid).then_some(pid)
+}
+
+// A slot nobody else can touch until its handle is handed out
+pub struct SlabBuffer<'a> {
+    slab: &'a Slab,
+    index: usize,
+    len: usize,
+    generation: u32,
+}
+
+impl SlabBuffer<'_> {
+    // From here on the buffer is owned by whoever gets the handle,
+    // it's only back in the slab once they `free` it
+    pub fn into_handle(self) -> SlabHandle {
+        let handle = SlabHandle {
+            offset: (self.index * self.slab.layout.stride) as u64,
+            len: self.len as u32,
+            generation: self.generation,
+        };
+        std::mem::forget(self);
+        handle
+    }
+}
+
+impl Deref for SlabBuffer<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.slab.slot_ptr(self.index), self.len) }
+    }
+}
+
+impl DerefMut for SlabBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.slab.slot_ptr(self.index), self.len) }
+    }
+}
+
+impl Drop for SlabBuffer<'_> {
+    fn drop(&mut self) {
+        self.slab.states()[self.index].store(self.generation << 1 | FREE, Ordering::Relaxed);
+        self.slab.release(self.index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Reader, Writer};
+
+    #[test]
+    fn test_alloc_and_free() {
+        let prefix = crate::random_name();
+
+        let slab = Slab::create(&prefix, 100, 2).unwrap();
+        assert_eq!(
+            (slab.slot_size(), slab.slots(), slab.free_slots()),
+            (100, 2, 2)
+        );
+
+        let mut buffer = slab.alloc(3).unwrap();
+        buffer.copy_from_slice(b"123");
+        let first = buffer.into_handle();
+        let mut buffer = slab.alloc(100).unwrap();
+        buffer.fill(b'4');
+        let second = buffer.into_handle();
+        assert_ne!(first.offset, second.offset);
+        assert_eq!(slab.free_slots(), 0);
+        assert!(matches!(slab.alloc(1), Err(SlabError::Full)));
+
+        // somewhere else
+        let other = Slab::open(&prefix).unwrap();
+        assert_eq!(other.get(first).unwrap(), b"123");
+        assert_eq!(other.get(second).unwrap(), [b'4'; 100]);
+        other.free(first).unwrap();
+        assert_eq!(slab.free_slots(), 1);
+
+        // a dropped buffer goes straight back
+        drop(slab.alloc(1).unwrap());
+        assert_eq!(slab.free_slots(), 1);
+        let third = slab.alloc(1).unwrap().into_handle();
+        assert_eq!(third.offset, first.offset);
+    }
+
+    #[test]
+    fn test_invalid_handles() {
+        let prefix = crate::random_name();
+
+        let slab = Slab::create(&prefix, 100, 2).unwrap();
+        assert!(matches!(
+            slab.alloc(101),
+            Err(SlabError::TooLarge { len: 101, max: 100 })
+        ));
+
+        let handle = slab.alloc(3).unwrap().into_handle();
+        slab.free(handle).unwrap();
+        // double free
+        assert!(matches!(
+            slab.free(handle),
+            Err(SlabError::InvalidHandle(_))
+        ));
+        assert!(slab.get(handle).is_err());
+
+        for handle in [
+            SlabHandle {
+                offset: 1,
+                len: 3,
+                generation: 1,
+            },
+            SlabHandle {
+                offset: 1 << 20,
+                len: 3,
+                generation: 1,
+            },
+            SlabHandle {
+                offset: 0,
+                len: 101,
+                generation: 1,
+            },
+        ] {
+            assert!(matches!(slab.get(handle), Err(SlabError::InvalidHandle(_))));
+        }
+        assert_eq!(slab.free_slots(), 2);
+    }
+
+    #[test]
+    fn test_stale_handle() {
+        let prefix = crate::random_name();
+
+        let slab = Slab::create(&prefix, 100, 1).unwrap();
+        let mut buffer = slab.alloc(3).unwrap();
+        buffer.copy_from_slice(b"111");
+        let stale = buffer.into_handle();
+        slab.free(stale).unwrap();
+
+        // the same slot, somebody else's now
+        let mut buffer = slab.alloc(3).unwrap();
+        buffer.copy_from_slice(b"222");
+        let handle = buffer.into_handle();
+        assert_eq!(handle.offset, stale.offset);
+        assert_ne!(handle.generation, stale.generation);
+
+        assert!(matches!(slab.free(stale), Err(SlabError::InvalidHandle(h)) if h == stale));
+        assert!(matches!(slab.get(stale), Err(SlabError::InvalidHandle(_))));
+        assert_eq!(slab.get(handle).unwrap(), b"222");
+        assert_eq!(slab.free_slots(), 0);
+
+        // a dropped buffer moves the generation on as well
+        slab.free(handle).unwrap();
+        drop(slab.alloc(3).unwrap());
+        let next = slab.alloc(3).unwrap().into_handle();
+        assert!(slab.free(handle).is_err());
+        slab.free(next).unwrap();
+
+        let handle = SlabHandle::from_bytes(&next.to_bytes()).unwrap();
+        assert_eq!(handle, next);
+    }
+
+    #[test]
+    fn test_open() {
+        let prefix = crate::random_name();
+
+        let err = Slab::open(&prefix).unwrap_err();
+        assert!(matches!(err, SlabError::OpenError(_)));
+        assert_eq!(
+            std::io::Error::from(err).kind(),
+            std::io::ErrorKind::NotFound
+        );
+
+        let slab = Slab::create(&prefix, 10, 1).unwrap();
+        drop(Slab::open(&prefix).unwrap());
+        // unlinked by its owner only
+        assert!(Slab::open(&prefix).is_ok());
+        drop(slab);
+        assert!(Slab::open(&prefix).is_err());
+    }
+
+    #[test]
+    fn test_in_use() {
+        let prefix = crate::random_name();
+
+        let slab = Slab::create(&prefix, 10, 1).unwrap();
+        let consumer = Slab::open(&prefix).unwrap();
+        let err = Slab::create(&prefix, 10, 1).unwrap_err();
+        assert!(matches!(&err, SlabError::InUse { pid, .. } if *pid == std::process::id()));
+        assert_eq!(
+            std::io::Error::from(err).kind(),
+            std::io::ErrorKind::AddrInUse
+        );
+
+        // still the same segment
+        let handle = slab.alloc(1).unwrap().into_handle();
+        consumer.free(handle).unwrap();
+
+        // left behind by an owner that is gone
+        slab.header()
+            .owner_pid
+            .store(i32::MAX as u32, Ordering::Relaxed);
+        std::mem::forget(slab);
+        let slab = Slab::create(&prefix, 10, 1).unwrap();
+        drop(slab);
+        Slab::create(&prefix, 10, 1).unwrap();
+    }
+
+    #[test]
+    fn test_handles_through_queue() {
+        let prefix = crate::random_name();
+
+        let slab = Slab::create(&prefix, 1 << 16, 4).unwrap();
+        let mut writer = Writer::<1_000>::new(&prefix).unwrap();
+        let mut reader = Reader::<1_000>::new(&prefix).unwrap();
+        let consumer = Slab::open(&prefix).unwrap();
+
+        for i in 0..10u8 {
+            let mut buffer = slab.alloc(1 << 16).unwrap();
+            buffer.fill(i);
+            writer.ipc_push(&buffer.into_handle().to_bytes()).unwrap();
+
+            let message = reader.ipc_pop().unwrap().unwrap();
+            let handle = SlabHandle::from_bytes(&message).unwrap();
+            assert!(consumer.get(handle).unwrap().iter().all(|&byte| byte == i));
+            consumer.free(handle).unwrap();
+        }
+        assert_eq!(slab.free_slots(), 4);
+    }
+
+    #[test]
+    fn test_concurrent() {
+        let prefix = crate::random_name();
+        let slab = Slab::create(&prefix, 8, 16).unwrap();
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let prefix = prefix.clone();
+                std::thread::spawn(move || {
+                    let slab = Slab::open(&prefix).unwrap();
+                    for i in 0..10_000u64 {
+                        let mut buffer = match slab.alloc(8) {
+                            Ok(buffer) => buffer,
+                            Err(SlabError::Full) => continue,
+                            Err(err) => panic!("{}", err),
+                        };
+                        buffer.copy_from_slice(&i.to_le_bytes());
+                        let handle = buffer.into_handle();
+                        assert_eq!(slab.get(handle).unwrap(), i.to_le_bytes());
+                        slab.free(handle).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(slab.free_slots(), 16);
+        let handles: Vec<_> = (0..16)
+            .map(|_| slab.alloc(8).unwrap().into_handle())
+            .collect();
+        let mut offsets: Vec<_> = handles.iter().map(|handle| handle.offset).collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+        assert_eq!(offsets.len(), 16);
+    }
+}
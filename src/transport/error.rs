@@ -0,0 +1,75 @@
+use std::{error::Error, fmt, io};
+
+use crate::{ReaderError, WriterError};
+
+#[derive(Debug)]
+pub enum TransportError {
+    WriterError(WriterError),
+    ReaderError(ReaderError),
+    SocketError {
+        operation: &'static str,
+        path: String,
+        source: io::Error,
+    },
+    // nothing to connect to, the writer isn't there (yet)
+    NotFound {
+        prefix: String,
+    },
+    // the writer has gone away
+    Disconnected,
+    // another writer is still listening on the socket
+    InUse {
+        path: String,
+    },
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WriterError(_) => f.write_str("shm writer failed"),
+            Self::ReaderError(_) => f.write_str("shm reader failed"),
+            Self::SocketError {
+                operation, path, ..
+            } => write!(f, "{} failed for socket {:?}", operation, path),
+            Self::NotFound { prefix } => write!(f, "no writer for {:?}", prefix),
+            Self::Disconnected => f.write_str("the writer has disconnected"),
+            Self::InUse { path } => write!(f, "socket {:?} is in use by another writer", path),
+        }
+    }
+}
+
+impl Error for TransportError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::WriterError(err) => Some(err),
+            Self::ReaderError(err) => Some(err),
+            Self::SocketError { source, .. } => Some(source),
+            Self::NotFound { .. } | Self::Disconnected | Self::InUse { .. } => None,
+        }
+    }
+}
+
+impl From<TransportError> for io::Error {
+    fn from(err: TransportError) -> Self {
+        match err {
+            TransportError::WriterError(err) => err.into(),
+            TransportError::ReaderError(err) => err.into(),
+            TransportError::SocketError { ref source, .. } => io::Error::new(source.kind(), err),
+            TransportError::NotFound { .. } => io::Error::new(io::ErrorKind::NotFound, err),
+            TransportError::Disconnected => io::Error::new(io::ErrorKind::ConnectionReset, err),
+            TransportError::InUse { .. } => io::Error::new(io::ErrorKind::AddrInUse, err),
+        }
+    }
+}
+
+impl From<WriterError> for TransportError {
+    fn from(err: WriterError) -> Self {
+        Self::WriterError(err)
+    }
+}
+
+impl From<ReaderError> for TransportError {
+    fn from(err: ReaderError) -> Self {
+        Self::ReaderError(err)
+    }
+}
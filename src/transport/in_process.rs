@@ -0,0 +1,144 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::transport::{Transport, TransportError, TransportReader, TransportWriter};
+
+#[derive(Default)]
+struct Shared {
+    messages: Mutex<VecDeque<Vec<u8>>>,
+    writer_gone: AtomicBool,
+}
+
+type Queue = Arc<Shared>;
+
+// Queues of the writers of this process by prefix, the counterpart of /dev/shm
+static QUEUES: Mutex<Option<HashMap<String, Queue>>> = Mutex::new(None);
+
+fn queues<T>(f: impl FnOnce(&mut HashMap<String, Queue>) -> T) -> T {
+    let mut queues = QUEUES.lock().unwrap_or_else(|err| err.into_inner());
+    f(queues.get_or_insert_with(HashMap::new))
+}
+
+// Both sides in the same process, meant for tests of code that
+// is otherwise configured with one of the other transports
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InProcessTransport;
+
+impl Transport for InProcessTransport {
+    fn writer(&self, prefix: &str) -> Result<Box<dyn TransportWriter>, TransportError> {
+        Ok(Box::new(InProcessWriter::new(prefix)))
+    }
+
+    fn reader(&self, prefix: &str) -> Result<Box<dyn TransportReader>, TransportError> {
+        Ok(Box::new(InProcessReader::new(prefix)?))
+    }
+}
+
+pub struct InProcessWriter {
+    queue: Queue,
+    prefix: String,
+}
+
+impl InProcessWriter {
+    // Like a restarted shm writer, it replaces whatever has been there
+    pub fn new(prefix: &str) -> Self {
+        let queue = Queue::default();
+        queues(|queues| queues.insert(prefix.to_string(), queue.clone()));
+        Self {
+            queue,
+            prefix: prefix.to_string(),
+        }
+    }
+}
+
+impl TransportWriter for InProcessWriter {
+    fn push(&mut self, message: &[u8]) -> Result<(), TransportError> {
+        let mut messages = self
+            .queue
+            .messages
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        messages.push_back(message.to_vec());
+        Ok(())
+    }
+}
+
+impl Drop for InProcessWriter {
+    fn drop(&mut self) {
+        self.queue.writer_gone.store(true, Ordering::Release);
+        // unless another writer has taken over
+        queues(|queues| {
+            if queues
+                .get(&self.prefix)
+                .is_some_and(|queue| Arc::ptr_eq(queue, &self.queue))
+            {
+                queues.remove(&self.prefix);
+            }
+        });
+    }
+}
+
+pub struct InProcessReader {
+    queue: Queue,
+}
+
+impl InProcessReader {
+    pub fn new(prefix: &str) -> Result<Self, TransportError> {
+        let queue = queues(|queues| queues.get(prefix).cloned());
+        match queue {
+            Some(queue) => Ok(Self { queue }),
+            None => Err(TransportError::NotFound {
+                prefix: prefix.to_string(),
+            }),
+        }
+    }
+}
+
+impl TransportReader for InProcessReader {
+    fn pop_into(&mut self, buffer: &mut Vec<u8>) -> Result<bool, TransportError> {
+        buffer.clear();
+        // checked first, so that the last message isn't missed
+        let writer_gone = self.queue.writer_gone.load(Ordering::Acquire);
+        let mut messages = self
+            .queue
+            .messages
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        match messages.pop_front() {
+            Some(message) => {
+                buffer.extend_from_slice(&message);
+                Ok(true)
+            }
+            // whatever the writer has left is still popped
+            None if writer_gone => Err(TransportError::Disconnected),
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writer_gone() {
+        let prefix = crate::random_name();
+
+        let mut writer = InProcessWriter::new(&prefix);
+        let mut reader = InProcessReader::new(&prefix).unwrap();
+        writer.push(b"111").unwrap();
+        drop(writer);
+
+        assert_eq!(reader.pop().unwrap(), Some(b"111".to_vec()));
+        assert!(matches!(reader.pop(), Err(TransportError::Disconnected)));
+        assert!(matches!(
+            InProcessReader::new(&prefix),
+            Err(TransportError::NotFound { .. })
+        ));
+    }
+}
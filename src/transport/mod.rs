@@ -0,0 +1,168 @@
+mod error;
+pub use error::TransportError;
+
+mod in_process;
+pub use in_process::{InProcessReader, InProcessTransport, InProcessWriter};
+
+mod shm;
+pub use shm::ShmTransport;
+
+mod unix_socket;
+pub use unix_socket::{UnixSocketReader, UnixSocketTransport, UnixSocketWriter};
+
+use std::{path::PathBuf, str::FromStr};
+
+// Where messages go between a writer and its reader. Shared memory
+// (see `Writer` and `Reader`) is the fastest, a Unix socket needs
+// no room in /dev/shm, and the in-process one needs no OS resources
+// at all. Every backend keeps what's pushed before the reader
+// connects, and a reader can only connect once the writer is up.
+// Nothing runs in the background though: a writer that may go idle
+// has to `flush` every now and then, or whatever the socket one
+// holds on to is only handed over on its next push.
+// It's an API of its own next to `Writer` and `Reader`, shared memory
+// goes through them unchanged and keeps what only they can do
+// (batches, `pop_ref`, channels)
+pub trait Transport {
+    fn writer(&self, prefix: &str) -> Result<Box<dyn TransportWriter>, TransportError>;
+    fn reader(&self, prefix: &str) -> Result<Box<dyn TransportReader>, TransportError>;
+}
+
+pub trait TransportWriter {
+    fn push(&mut self, message: &[u8]) -> Result<(), TransportError>;
+
+    // Hands over whatever the reader couldn't take yet, or has connected
+    // after it was pushed, if the transport has to hold on to anything
+    // (otherwise it's done on the next push)
+    fn flush(&mut self) -> Result<(), TransportError> {
+        Ok(())
+    }
+}
+
+pub trait TransportReader {
+    // Returns false if there's nothing to pop right now, and `Disconnected`
+    // once the writer is gone and everything it's left has been popped
+    fn pop_into(&mut self, buffer: &mut Vec<u8>) -> Result<bool, TransportError>;
+
+    fn pop(&mut self) -> Result<Option<Vec<u8>>, TransportError> {
+        let mut buffer = vec![];
+        Ok(self.pop_into(&mut buffer)?.then_some(buffer))
+    }
+}
+
+// Which transport to use, as it would be written in a config file:
+// "shm", "unix" (sockets in the temp dir), "unix:<dir>" or "in-process"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportConfig {
+    Shm,
+    UnixSocket { dir: PathBuf },
+    InProcess,
+}
+
+impl TransportConfig {
+    // Shared memory segments are QUEUE_SIZE bytes, the others ignore it
+    pub fn build<const QUEUE_SIZE: usize>(&self) -> Box<dyn Transport> {
+        match self {
+            Self::Shm => Box::new(ShmTransport::<QUEUE_SIZE>::default()),
+            Self::UnixSocket { dir } => Box::new(UnixSocketTransport::new(dir)),
+            Self::InProcess => Box::new(InProcessTransport),
+        }
+    }
+}
+
+impl FromStr for TransportConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shm" => Ok(Self::Shm),
+            "unix" => Ok(Self::UnixSocket {
+                dir: std::env::temp_dir(),
+            }),
+            "in-process" => Ok(Self::InProcess),
+            _ => match s.strip_prefix("unix:") {
+                Some(dir) if !dir.is_empty() => Ok(Self::UnixSocket { dir: dir.into() }),
+                _ => Err(format!("unknown transport {:?}", s)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn configs() -> Vec<TransportConfig> {
+        ["shm", "unix", "in-process"]
+            .into_iter()
+            .map(|config| config.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for config in configs() {
+            let transport = config.build::<1_000>();
+            let prefix = crate::random_name();
+
+            assert!(transport.reader(&prefix).is_err(), "{:?}", config);
+
+            let mut writer = transport.writer(&prefix).unwrap();
+            // before the reader is there
+            writer.push(b"111").unwrap();
+            let mut reader = transport.reader(&prefix).unwrap();
+            writer.flush().unwrap();
+            assert_eq!(reader.pop().unwrap(), Some(b"111".to_vec()), "{:?}", config);
+
+            writer.push(b"").unwrap();
+            writer.push(&[3; 2_000]).unwrap();
+
+            assert_eq!(reader.pop().unwrap(), Some(vec![]), "{:?}", config);
+            let mut buffer = vec![];
+            assert!(reader.pop_into(&mut buffer).unwrap());
+            assert_eq!(buffer, vec![3; 2_000]);
+            assert!(!reader.pop_into(&mut buffer).unwrap());
+            assert!(buffer.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_disconnected() {
+        for config in configs() {
+            let transport = config.build::<1_000>();
+            let prefix = crate::random_name();
+
+            let mut writer = transport.writer(&prefix).unwrap();
+            let mut reader = transport.reader(&prefix).unwrap();
+            writer.push(b"111").unwrap();
+            writer.flush().unwrap();
+            drop(writer);
+
+            assert_eq!(reader.pop().unwrap(), Some(b"111".to_vec()), "{:?}", config);
+            // the shm reader only checks every now and then
+            let deadline = Instant::now() + Duration::from_secs(1);
+            loop {
+                match reader.pop() {
+                    Err(TransportError::Disconnected) => break,
+                    Ok(None) if Instant::now() < deadline => std::thread::yield_now(),
+                    other => panic!("{:?}: {:?}", config, other),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!("shm".parse(), Ok(TransportConfig::Shm));
+        assert_eq!(
+            "unix:/run/ipc".parse(),
+            Ok(TransportConfig::UnixSocket {
+                dir: "/run/ipc".into()
+            })
+        );
+        assert_eq!("in-process".parse(), Ok(TransportConfig::InProcess));
+        assert!("unix:".parse::<TransportConfig>().is_err());
+        assert!("tcp".parse::<TransportConfig>().is_err());
+    }
+}
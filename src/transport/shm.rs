@@ -0,0 +1,42 @@
+use crate::{
+    transport::{Transport, TransportError, TransportReader, TransportWriter},
+    Reader, ReaderOptions, Writer, WriterOptions,
+};
+
+// `Writer` and `Reader` as they are, see `Transport`
+#[derive(Debug, Clone, Default)]
+pub struct ShmTransport<const QUEUE_SIZE: usize> {
+    pub writer_options: WriterOptions,
+    pub reader_options: ReaderOptions,
+}
+
+impl<const QUEUE_SIZE: usize> Transport for ShmTransport<QUEUE_SIZE> {
+    fn writer(&self, prefix: &str) -> Result<Box<dyn TransportWriter>, TransportError> {
+        let writer = Writer::<QUEUE_SIZE>::with_options(prefix, self.writer_options.clone())?;
+        Ok(Box::new(writer))
+    }
+
+    fn reader(&self, prefix: &str) -> Result<Box<dyn TransportReader>, TransportError> {
+        let reader = Reader::<QUEUE_SIZE>::with_options(prefix, self.reader_options.clone())?;
+        Ok(Box::new(reader))
+    }
+}
+
+impl<const QUEUE_SIZE: usize> TransportWriter for Writer<QUEUE_SIZE> {
+    fn push(&mut self, message: &[u8]) -> Result<(), TransportError> {
+        Ok(self.ipc_push(message)?)
+    }
+}
+
+impl<const QUEUE_SIZE: usize> TransportReader for Reader<QUEUE_SIZE> {
+    fn pop_into(&mut self, buffer: &mut Vec<u8>) -> Result<bool, TransportError> {
+        if Reader::pop_into(self, buffer)? {
+            return Ok(true);
+        }
+        // whatever the writer has left is popped first
+        if self.is_writer_gone() {
+            return Err(TransportError::Disconnected);
+        }
+        Ok(false)
+    }
+}
@@ -0,0 +1,388 @@
+use std::{
+    collections::VecDeque,
+    ffi::{CStr, CString},
+    io::{self, IoSlice, IoSliceMut},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+};
+
+use libc::{EAGAIN, SOCK_NONBLOCK, SOCK_SEQPACKET};
+
+use crate::{
+    capi::{accept, bind, close, connect, listen, peek_len, recv_vectored, send_vectored, socket},
+    event::event,
+    transport::{Transport, TransportError, TransportReader, TransportWriter},
+};
+
+// Every packet starts with this byte, so that an empty message
+// isn't mistaken for the writer hanging up (both are 0 bytes long)
+const MESSAGE: u8 = 0;
+
+// One SOCK_SEQPACKET socket per channel, `{dir}/{prefix}.sock`, for when
+// /dev/shm is too small (or not there at all). The writer listens and the
+// reader connects, message boundaries are kept by the socket itself.
+// Messages are limited by the socket buffer size.
+// The writer only accepts the reader and sends what's pending
+// on `push` and `flush`, see `Transport`
+#[derive(Debug, Clone)]
+pub struct UnixSocketTransport {
+    dir: PathBuf,
+}
+
+impl UnixSocketTransport {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, prefix: &str) -> PathBuf {
+        self.dir.join(format!("{}.sock", prefix))
+    }
+}
+
+impl Transport for UnixSocketTransport {
+    fn writer(&self, prefix: &str) -> Result<Box<dyn TransportWriter>, TransportError> {
+        Ok(Box::new(UnixSocketWriter::new(self.path(prefix))?))
+    }
+
+    fn reader(&self, prefix: &str) -> Result<Box<dyn TransportReader>, TransportError> {
+        Ok(Box::new(UnixSocketReader::new(self.path(prefix))?))
+    }
+}
+
+fn socket_error(operation: &'static str, path: &Path) -> impl FnOnce(io::Error) -> TransportError {
+    let path = path.display().to_string();
+    move |source| TransportError::SocketError {
+        operation,
+        path,
+        source,
+    }
+}
+
+// A nul in the path is reported as a failure of what it's meant for
+fn c_path(path: &Path, operation: &'static str) -> Result<CString, TransportError> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|err| {
+        socket_error(operation, path)(io::Error::new(io::ErrorKind::InvalidInput, err))
+    })
+}
+
+pub struct UnixSocketWriter {
+    listener: i32,
+    // the reader, once it has connected
+    connection: Option<i32>,
+    // pushed while there is no reader, or it isn't keeping up,
+    // like messages piling up in shm segments
+    pending: VecDeque<Vec<u8>>,
+    path: PathBuf,
+    // device and inode of the socket file once it's bound,
+    // it's only removed on drop if it's still the same one
+    inode: Option<(u64, u64)>,
+}
+
+impl UnixSocketWriter {
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self, TransportError> {
+        let path = path.into();
+        let c_path = c_path(&path, "bind")?;
+
+        if listening(&path, &c_path)? {
+            return Err(TransportError::InUse {
+                path: path.display().to_string(),
+            });
+        }
+        // left behind by a previous writer
+        let _ = std::fs::remove_file(&path);
+
+        let listener =
+            socket(SOCK_SEQPACKET | SOCK_NONBLOCK).map_err(socket_error("socket", &path))?;
+        let mut writer = Self {
+            listener,
+            connection: None,
+            pending: VecDeque::new(),
+            path,
+            inode: None,
+        };
+        bind(listener, &c_path).map_err(socket_error("bind", &writer.path))?;
+        writer.inode = inode(&writer.path);
+        listen(listener, 1).map_err(socket_error("listen", &writer.path))?;
+
+        event!(
+            debug,
+            "writer.socket_listening",
+            path = writer.path.display().to_string(),
+        );
+
+        Ok(writer)
+    }
+
+    fn accept(&mut self) -> Result<(), TransportError> {
+        if self.connection.is_some() {
+            return Ok(());
+        }
+        match accept(self.listener) {
+            Ok(fd) => {
+                event!(
+                    debug,
+                    "writer.socket_accepted",
+                    path = self.path.display().to_string(),
+                );
+                self.connection = Some(fd);
+                Ok(())
+            }
+            Err(err) if err.raw_os_error() == Some(EAGAIN) => Ok(()),
+            Err(err) => Err(socket_error("accept", &self.path)(err)),
+        }
+    }
+
+    // Returns false if the reader has no room for it right now
+    // (or there is no reader)
+    fn send(&mut self, message: &[u8]) -> Result<bool, TransportError> {
+        let fd = match self.connection {
+            Some(fd) => fd,
+            None => return Ok(false),
+        };
+        match send_vectored(fd, &[IoSlice::new(&[MESSAGE]), IoSlice::new(message)]) {
+            Ok(_) => Ok(true),
+            Err(err) if err.raw_os_error() == Some(EAGAIN) => Ok(false),
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset
+                ) =>
+            {
+                // the next reader gets this one and whatever is pending,
+                // what's still in the socket buffer is gone with it
+                event!(
+                    debug,
+                    "writer.socket_reader_gone",
+                    path = self.path.display().to_string(),
+                );
+                let _ = close(fd);
+                self.connection = None;
+                Ok(false)
+            }
+            Err(err) => Err(socket_error("sendmsg", &self.path)(err)),
+        }
+    }
+}
+
+impl TransportWriter for UnixSocketWriter {
+    fn push(&mut self, message: &[u8]) -> Result<(), TransportError> {
+        self.flush()?;
+        if !self.pending.is_empty() || !self.send(message)? {
+            self.pending.push_back(message.to_vec());
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), TransportError> {
+        self.accept()?;
+        while let Some(message) = self.pending.pop_front() {
+            if !self.send(&message)? {
+                self.pending.push_front(message);
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for UnixSocketWriter {
+    fn drop(&mut self) {
+        if let Some(fd) = self.connection {
+            let _ = close(fd);
+        }
+        let _ = close(self.listener);
+        // unless another writer has bound it since
+        if self.inode.is_some() && inode(&self.path) == self.inode {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn inode(path: &Path) -> Option<(u64, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+// Whether a writer is listening on `path`. It may take the probe for
+// its reader, which is closed by then, so it just moves on to the next
+// one (see `UnixSocketWriter::send`) and keeps what it's pushed
+fn listening(path: &Path, c_path: &CStr) -> Result<bool, TransportError> {
+    let fd = socket(SOCK_SEQPACKET | SOCK_NONBLOCK).map_err(socket_error("socket", path))?;
+    let connected = connect(fd, c_path);
+    let _ = close(fd);
+    match connected {
+        Ok(()) => Ok(true),
+        // its backlog is full
+        Err(err) if err.raw_os_error() == Some(EAGAIN) => Ok(true),
+        // nothing there, or a socket nobody listens on anymore
+        Err(_) => Ok(false),
+    }
+}
+
+pub struct UnixSocketReader {
+    fd: i32,
+    path: PathBuf,
+}
+
+impl UnixSocketReader {
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self, TransportError> {
+        let path = path.into();
+        let c_path = c_path(&path, "connect")?;
+
+        let fd = socket(SOCK_SEQPACKET).map_err(socket_error("socket", &path))?;
+        let reader = Self { fd, path };
+        connect(fd, &c_path).map_err(socket_error("connect", &reader.path))?;
+        Ok(reader)
+    }
+}
+
+impl TransportReader for UnixSocketReader {
+    fn pop_into(&mut self, buffer: &mut Vec<u8>) -> Result<bool, TransportError> {
+        buffer.clear();
+        let len = match peek_len(self.fd) {
+            Ok(0) => return Err(TransportError::Disconnected),
+            Ok(len) => len,
+            Err(err) if err.raw_os_error() == Some(EAGAIN) => return Ok(false),
+            Err(err) => return Err(socket_error("recv", &self.path)(err)),
+        };
+
+        let mut kind = [0];
+        buffer.resize(len - 1, 0);
+        recv_vectored(
+            self.fd,
+            &mut [IoSliceMut::new(&mut kind), IoSliceMut::new(buffer)],
+        )
+        .map_err(socket_error("recvmsg", &self.path))?;
+        Ok(true)
+    }
+}
+
+impl Drop for UnixSocketReader {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reader_gone() {
+        let transport = UnixSocketTransport::new(std::env::temp_dir());
+        let prefix = crate::random_name();
+
+        let mut writer = transport.writer(&prefix).unwrap();
+        let mut reader = transport.reader(&prefix).unwrap();
+        writer.push(b"111").unwrap();
+        assert_eq!(reader.pop().unwrap(), Some(b"111".to_vec()));
+        // sent, but never popped
+        writer.push(b"lost").unwrap();
+        drop(reader);
+
+        // kept for the next one
+        writer.push(b"222").unwrap();
+        writer.push(b"333").unwrap();
+        let mut reader = transport.reader(&prefix).unwrap();
+        writer.push(b"444").unwrap();
+        for message in [b"222", b"333", b"444"] {
+            assert_eq!(reader.pop().unwrap(), Some(message.to_vec()));
+        }
+        assert_eq!(reader.pop().unwrap(), None);
+
+        drop(writer);
+        assert!(matches!(reader.pop(), Err(TransportError::Disconnected)));
+        assert!(!transport.path(&prefix).exists());
+        assert!(matches!(
+            transport.reader(&prefix).err().unwrap(),
+            TransportError::SocketError {
+                operation: "connect",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_in_use() {
+        let transport = UnixSocketTransport::new(std::env::temp_dir());
+        let prefix = crate::random_name();
+
+        let mut writer = transport.writer(&prefix).unwrap();
+        let mut reader = transport.reader(&prefix).unwrap();
+        let err = transport.writer(&prefix).err().unwrap();
+        assert!(matches!(err, TransportError::InUse { .. }));
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::AddrInUse);
+
+        writer.push(b"111").unwrap();
+        assert_eq!(reader.pop().unwrap(), Some(b"111".to_vec()));
+
+        // a socket file nobody listens on anymore is replaced
+        drop(reader);
+        drop(writer);
+        let path = transport.path(&prefix);
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        let mut writer = transport.writer(&prefix).unwrap();
+        let mut reader = transport.reader(&prefix).unwrap();
+        writer.push(b"222").unwrap();
+        assert_eq!(reader.pop().unwrap(), Some(b"222".to_vec()));
+    }
+
+    #[test]
+    fn test_path_taken_over() {
+        let transport = UnixSocketTransport::new(std::env::temp_dir());
+        let prefix = crate::random_name();
+        let path = transport.path(&prefix);
+
+        let first = transport.writer(&prefix).unwrap();
+        // removed behind its back, and bound by another writer
+        std::fs::remove_file(&path).unwrap();
+        let mut second = transport.writer(&prefix).unwrap();
+        drop(first);
+
+        assert!(path.exists());
+        let mut reader = transport.reader(&prefix).unwrap();
+        second.push(b"111").unwrap();
+        assert_eq!(reader.pop().unwrap(), Some(b"111".to_vec()));
+    }
+
+    #[test]
+    fn test_idle_writer() {
+        let transport = UnixSocketTransport::new(std::env::temp_dir());
+        let prefix = crate::random_name();
+
+        let mut writer = transport.writer(&prefix).unwrap();
+        writer.push(b"111").unwrap();
+        let mut reader = transport.reader(&prefix).unwrap();
+
+        // the writer hasn't accepted it yet
+        assert_eq!(reader.pop().unwrap(), None);
+        writer.flush().unwrap();
+        assert_eq!(reader.pop().unwrap(), Some(b"111".to_vec()));
+    }
+
+    #[test]
+    fn test_slow_reader() {
+        let transport = UnixSocketTransport::new(std::env::temp_dir());
+        let prefix = crate::random_name();
+
+        let mut writer = transport.writer(&prefix).unwrap();
+        let mut reader = transport.reader(&prefix).unwrap();
+
+        // way more than the socket buffer
+        let message = [7; 10_000];
+        for _ in 0..1_000 {
+            writer.push(&message).unwrap();
+        }
+        let mut popped = 0;
+        let mut buffer = vec![];
+        while popped < 1_000 {
+            if reader.pop_into(&mut buffer).unwrap() {
+                assert_eq!(buffer, message);
+                popped += 1;
+            } else {
+                writer.flush().unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::any::type_name;
+
+pub type CodecError = Box<dyn std::error::Error + Send + Sync>;
+
+// How values of `T` are turned into messages and back
+pub trait Codec<T> {
+    // Goes into the type fingerprint along with the name of `T`,
+    // a codec whose encoding changes should change its name too
+    const NAME: &'static str;
+
+    // `buffer` is empty, and reused from one message to the next
+    fn encode(value: &T, buffer: &mut Vec<u8>) -> Result<(), CodecError>;
+
+    fn decode(bytes: &[u8]) -> Result<T, CodecError>;
+
+    // Stored in the root segment by `TypedWriter` and checked by `TypedReader`.
+    // Type names are only stable for a given compiler, override it
+    // (with a schema version, say) if both sides may be built separately
+    fn fingerprint() -> u64 {
+        fingerprint(&[type_name::<T>(), Self::NAME])
+    }
+}
+
+// FNV-1a over the parts, never 0 as that's what plain byte channels have
+pub fn fingerprint(parts: &[&str]) -> u64 {
+    let hash = parts
+        .iter()
+        .flat_map(|part| part.bytes().chain([0]))
+        .fold(0xcbf29ce484222325, |hash: u64, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    hash.max(1)
+}
+
+// Messages as they are
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RawCodec;
+
+impl Codec<Vec<u8>> for RawCodec {
+    const NAME: &'static str = "raw";
+
+    fn encode(value: &Vec<u8>, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        buffer.extend_from_slice(value);
+        Ok(())
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Vec<u8>, CodecError> {
+        Ok(bytes.to_vec())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Utf8Codec;
+
+impl Codec<String> for Utf8Codec {
+    const NAME: &'static str = "utf8";
+
+    fn encode(value: &String, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        buffer.extend_from_slice(value.as_bytes());
+        Ok(())
+    }
+
+    fn decode(bytes: &[u8]) -> Result<String, CodecError> {
+        Ok(String::from_utf8(bytes.to_vec())?)
+    }
+}
+
+// Anything serde can handle, encoded with bincode
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for BincodeCodec {
+    const NAME: &'static str = "bincode-1";
+
+    fn encode(value: &T, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        Ok(bincode::serialize_into(buffer, value)?)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<T, CodecError> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(
+            <RawCodec as Codec<Vec<u8>>>::fingerprint(),
+            <RawCodec as Codec<Vec<u8>>>::fingerprint()
+        );
+        assert_ne!(
+            <RawCodec as Codec<Vec<u8>>>::fingerprint(),
+            <Utf8Codec as Codec<String>>::fingerprint()
+        );
+        // parts don't run into each other
+        assert_ne!(fingerprint(&["ab", "c"]), fingerprint(&["a", "bc"]));
+        assert_ne!(fingerprint(&[]), 0);
+    }
+}
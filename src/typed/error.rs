@@ -0,0 +1,68 @@
+use std::{error::Error, fmt, io};
+
+use crate::{typed::CodecError, ReaderError, WriterError};
+
+#[derive(Debug)]
+pub enum TypedError {
+    WriterError(WriterError),
+    ReaderError(ReaderError),
+    EncodeError(CodecError),
+    // the message is popped all the same
+    DecodeError { sequence: u64, source: CodecError },
+    // the writer is pushing some other type, or plain bytes (0)
+    FingerprintMismatch { expected: u64, found: u64 },
+}
+
+impl fmt::Display for TypedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WriterError(_) => f.write_str("failed to push a message"),
+            Self::ReaderError(_) => f.write_str("failed to pop a message"),
+            Self::EncodeError(_) => f.write_str("failed to encode a message"),
+            Self::DecodeError { sequence, .. } => {
+                write!(f, "failed to decode message {}", sequence)
+            }
+            Self::FingerprintMismatch { expected, found } => write!(
+                f,
+                "channel carries type {:016x}, expected {:016x}",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl Error for TypedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::WriterError(err) => Some(err),
+            Self::ReaderError(err) => Some(err),
+            Self::EncodeError(err) | Self::DecodeError { source: err, .. } => Some(err.as_ref()),
+            Self::FingerprintMismatch { .. } => None,
+        }
+    }
+}
+
+impl From<TypedError> for io::Error {
+    fn from(err: TypedError) -> Self {
+        match err {
+            TypedError::WriterError(err) => err.into(),
+            TypedError::ReaderError(err) => err.into(),
+            TypedError::EncodeError(_) => io::Error::new(io::ErrorKind::InvalidInput, err),
+            TypedError::DecodeError { .. } | TypedError::FingerprintMismatch { .. } => {
+                io::Error::new(io::ErrorKind::InvalidData, err)
+            }
+        }
+    }
+}
+
+impl From<WriterError> for TypedError {
+    fn from(err: WriterError) -> Self {
+        Self::WriterError(err)
+    }
+}
+
+impl From<ReaderError> for TypedError {
+    fn from(err: ReaderError) -> Self {
+        Self::ReaderError(err)
+    }
+}
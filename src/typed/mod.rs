@@ -0,0 +1,259 @@
+mod codec;
+#[cfg(feature = "serde")]
+pub use codec::BincodeCodec;
+pub use codec::{fingerprint, Codec, CodecError, RawCodec, Utf8Codec};
+
+mod error;
+pub use error::TypedError;
+
+use std::{marker::PhantomData, time::Duration};
+
+use crate::{Reader, ReaderError, ReaderOptions, Writer, WriterOptions};
+
+// A channel of `T` values, see `Codec`. What the writer pushes is
+// recorded in the root segment, so a reader expecting something else
+// fails to connect instead of decoding garbage
+pub struct TypedWriter<T, C: Codec<T>, const QUEUE_SIZE: usize> {
+    writer: Writer<QUEUE_SIZE>,
+    buffer: Vec<u8>,
+    codec: PhantomData<fn(&T) -> C>,
+}
+
+impl<T, C: Codec<T>, const QUEUE_SIZE: usize> TypedWriter<T, C, QUEUE_SIZE> {
+    pub fn new(prefix: impl Into<String>) -> Result<Self, TypedError> {
+        Self::with_options(prefix, WriterOptions::default())
+    }
+
+    pub fn with_options(
+        prefix: impl Into<String>,
+        options: WriterOptions,
+    ) -> Result<Self, TypedError> {
+        let writer = Writer::create(prefix.into(), options, C::fingerprint())?;
+        Ok(Self {
+            writer,
+            buffer: vec![],
+            codec: PhantomData,
+        })
+    }
+
+    pub fn push(&mut self, value: &T) -> Result<(), TypedError> {
+        self.buffer.clear();
+        C::encode(value, &mut self.buffer).map_err(TypedError::EncodeError)?;
+        self.writer.ipc_push(&self.buffer)?;
+        Ok(())
+    }
+
+    pub fn writer(&self) -> &Writer<QUEUE_SIZE> {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> Writer<QUEUE_SIZE> {
+        self.writer
+    }
+}
+
+pub struct TypedReader<T, C: Codec<T>, const QUEUE_SIZE: usize> {
+    reader: Reader<QUEUE_SIZE>,
+    // (expected, found) once a restarted writer turns out to push something else
+    mismatch: Option<(u64, u64)>,
+    codec: PhantomData<fn() -> (T, C)>,
+}
+
+impl<T, C: Codec<T>, const QUEUE_SIZE: usize> TypedReader<T, C, QUEUE_SIZE> {
+    pub fn new(prefix: &str) -> Result<Self, TypedError> {
+        Self::checked(Reader::new(prefix)?)
+    }
+
+    pub fn with_options(prefix: &str, options: ReaderOptions) -> Result<Self, TypedError> {
+        Self::checked(Reader::with_options(prefix, options)?)
+    }
+
+    pub fn connect_wait(prefix: &str, timeout: Duration) -> Result<Self, TypedError> {
+        Self::checked(Reader::connect_wait(prefix, timeout)?)
+    }
+
+    fn checked(reader: Reader<QUEUE_SIZE>) -> Result<Self, TypedError> {
+        check_fingerprint::<T, C, QUEUE_SIZE>(&reader)?;
+        Ok(Self {
+            reader,
+            mismatch: None,
+            codec: PhantomData,
+        })
+    }
+
+    // A restarted writer may push something else, so the fingerprint is
+    // checked again whenever that happens. The inner reader is attached to
+    // it by then, so from there on every pop fails, a new reader is needed
+    pub fn pop(&mut self) -> Result<Option<T>, TypedError> {
+        if let Some((expected, found)) = self.mismatch {
+            return Err(TypedError::FingerprintMismatch { expected, found });
+        }
+        let decoded = self.reader.pop_ref().map(|message| {
+            message.map(|message| {
+                C::decode(&message).map_err(|source| TypedError::DecodeError {
+                    sequence: message.sequence(),
+                    source,
+                })
+            })
+        });
+        match decoded {
+            Ok(decoded) => decoded.transpose(),
+            Err(err @ ReaderError::WriterRestarted { .. }) => {
+                let (expected, found) = (C::fingerprint(), self.reader.type_fingerprint());
+                if found != expected {
+                    self.mismatch = Some((expected, found));
+                    return Err(TypedError::FingerprintMismatch { expected, found });
+                }
+                Err(err.into())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn reader(&self) -> &Reader<QUEUE_SIZE> {
+        &self.reader
+    }
+
+    pub fn into_inner(self) -> Reader<QUEUE_SIZE> {
+        self.reader
+    }
+}
+
+fn check_fingerprint<T, C: Codec<T>, const QUEUE_SIZE: usize>(
+    reader: &Reader<QUEUE_SIZE>,
+) -> Result<(), TypedError> {
+    let expected = C::fingerprint();
+    let found = reader.type_fingerprint();
+    if found != expected {
+        return Err(TypedError::FingerprintMismatch { expected, found });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // even numbers only
+    struct EvenCodec;
+
+    impl Codec<u32> for EvenCodec {
+        const NAME: &'static str = "even";
+
+        fn encode(value: &u32, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+            if !value.is_multiple_of(2) {
+                return Err(format!("{} is odd", value).into());
+            }
+            buffer.extend_from_slice(&value.to_le_bytes());
+            Ok(())
+        }
+
+        fn decode(bytes: &[u8]) -> Result<u32, CodecError> {
+            Ok(u32::from_le_bytes(bytes.try_into()?))
+        }
+    }
+
+    #[test]
+    fn test_typed() {
+        let prefix = crate::random_name();
+
+        let mut writer = TypedWriter::<u32, EvenCodec, 1_000>::new(&prefix).unwrap();
+        let mut reader = TypedReader::<u32, EvenCodec, 1_000>::new(&prefix).unwrap();
+
+        writer.push(&2).unwrap();
+        assert!(matches!(writer.push(&3), Err(TypedError::EncodeError(_))));
+        writer.push(&4).unwrap();
+        assert_eq!(reader.pop().unwrap(), Some(2));
+        assert_eq!(reader.pop().unwrap(), Some(4));
+        assert_eq!(reader.pop().unwrap(), None);
+
+        // garbage pushed behind its back
+        let mut writer = writer.into_inner();
+        writer.ipc_push(b"123").unwrap();
+        assert!(matches!(
+            reader.pop(),
+            Err(TypedError::DecodeError { sequence: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_fingerprint_mismatch() {
+        let prefix = crate::random_name();
+
+        let _writer = TypedWriter::<String, Utf8Codec, 1_000>::new(&prefix).unwrap();
+        let err = TypedReader::<Vec<u8>, RawCodec, 1_000>::new(&prefix)
+            .map(|_| ())
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            TypedError::FingerprintMismatch { expected, found }
+                if expected == <RawCodec as Codec<Vec<u8>>>::fingerprint()
+                    && found == <Utf8Codec as Codec<String>>::fingerprint()
+        ));
+        assert_eq!(
+            std::io::Error::from(err).kind(),
+            std::io::ErrorKind::InvalidData
+        );
+
+        // plain bytes
+        let prefix = crate::random_name();
+        let _writer = Writer::<1_000>::new(&prefix).unwrap();
+        assert!(matches!(
+            TypedReader::<String, Utf8Codec, 1_000>::new(&prefix),
+            Err(TypedError::FingerprintMismatch { found: 0, .. })
+        ));
+        // while a plain reader doesn't care
+        let prefix = crate::random_name();
+        let _writer = TypedWriter::<String, Utf8Codec, 1_000>::new(&prefix).unwrap();
+        Reader::<1_000>::new(&prefix).unwrap();
+    }
+
+    #[test]
+    fn test_writer_restarted_with_another_type() {
+        let prefix = crate::random_name();
+
+        let writer = TypedWriter::<String, Utf8Codec, 1_000>::new(&prefix).unwrap();
+        let mut reader = TypedReader::<String, Utf8Codec, 1_000>::new(&prefix).unwrap();
+        drop(writer);
+
+        let mut writer = TypedWriter::<Vec<u8>, RawCodec, 1_000>::new(&prefix).unwrap();
+        writer.push(&vec![0xff]).unwrap();
+        assert!(matches!(
+            reader.pop(),
+            Err(TypedError::FingerprintMismatch { .. })
+        ));
+        // never decoded with the wrong codec
+        assert!(matches!(
+            reader.pop(),
+            Err(TypedError::FingerprintMismatch { .. })
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_bincode() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Point {
+            x: i64,
+            label: String,
+        }
+
+        let prefix = crate::random_name();
+
+        let mut writer = TypedWriter::<Point, BincodeCodec, 1_000>::new(&prefix).unwrap();
+        let mut reader = TypedReader::<Point, BincodeCodec, 1_000>::new(&prefix).unwrap();
+
+        let point = Point {
+            x: -1,
+            label: "a".repeat(300),
+        };
+        writer.push(&point).unwrap();
+        assert_eq!(reader.pop().unwrap(), Some(point));
+
+        // same codec, another type
+        assert_ne!(
+            <BincodeCodec as Codec<Point>>::fingerprint(),
+            <BincodeCodec as Codec<String>>::fingerprint()
+        );
+    }
+}
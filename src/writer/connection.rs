@@ -1,7 +1,9 @@
-use libc::{MAP_SHARED, O_CREAT, O_RDWR, PROT_WRITE, S_IRUSR, S_IWUSR};
+use libc::{MAP_SHARED, O_CREAT, O_EXCL, O_RDWR, PROT_WRITE, S_IRUSR, S_IWUSR};
 
 use crate::{
-    capi::{ftruncate, mmap, munmap, shm_open, shm_unlink},
+    capi::{close, ftruncate, mmap, munmap, posix_fallocate, shm_open, shm_unlink},
+    event::event,
+    segment::{Failure, SegmentOptions},
     writer::{
         error::{WriterConnectError, WriterDisconnectError},
         queue::Queue,
@@ -17,60 +19,129 @@ pub struct WriterConnection<const QUEUE_SIZE: usize> {
 }
 
 impl<const QUEUE_SIZE: usize> WriterConnection<QUEUE_SIZE> {
+    pub(crate) const SIZE: usize = Queue::<QUEUE_SIZE>::SIZE;
+
     pub fn new(connection_type: ConnectionType) -> Result<Self, WriterConnectError> {
+        Self::with_options(connection_type, &SegmentOptions::default())
+    }
+
+    pub fn with_options(
+        connection_type: ConnectionType,
+        options: &SegmentOptions,
+    ) -> Result<Self, WriterConnectError> {
         let fd = shm_open(
             connection_type.id(),
-            O_RDWR | O_CREAT,
+            O_RDWR | O_CREAT | O_EXCL,
             (S_IRUSR | S_IWUSR) as std::ffi::c_uint,
         )
-        .map_err(WriterConnectError::ShmOpenError)?;
+        .map_err(|source| WriterConnectError::ShmOpenError {
+            segment: connection_type.name(),
+            source,
+        })?;
 
-        ftruncate(fd, QUEUE_SIZE as i64).map_err(WriterConnectError::FtruncateError)?;
-
-        let addr = mmap(
-            std::ptr::null_mut(),
-            QUEUE_SIZE,
-            PROT_WRITE,
-            MAP_SHARED,
-            fd,
-            0,
-        )
-        .map_err(WriterConnectError::MmapError)?;
+        let mapped = allocate(fd, Queue::<QUEUE_SIZE>::SIZE, &connection_type).and_then(|()| {
+            mmap(
+                std::ptr::null_mut(),
+                Queue::<QUEUE_SIZE>::SIZE,
+                PROT_WRITE,
+                MAP_SHARED | options.mmap_flags(),
+                fd,
+                0,
+            )
+            .map_err(|source| WriterConnectError::MmapError {
+                segment: connection_type.name(),
+                source,
+            })
+        });
+        let addr = match mapped {
+            Ok(addr) => addr,
+            Err(err) => {
+                // nobody is going to use what there is of it
+                let _ = close(fd);
+                let _ = shm_unlink(connection_type.id());
+                return Err(err);
+            }
+        };
 
-        println!("writer: addr = {:?}", addr);
+        event!(
+            debug,
+            "writer.segment_created",
+            segment = connection_type.name(),
+            size = Queue::<QUEUE_SIZE>::SIZE,
+            addr = addr,
+        );
 
-        let conn = Self {
+        // released on drop if anything below fails
+        let connection = Self {
             fd,
             addr,
             connection_type,
         };
-        println!("[Writer] Connected {:?}", conn.id());
+        options
+            .apply(addr, Queue::<QUEUE_SIZE>::SIZE, true)
+            .map_err(|failure| {
+                let segment = connection.name();
+                match failure {
+                    Failure::Madvise(source) => {
+                        WriterConnectError::MadviseError { segment, source }
+                    }
+                    Failure::Mlock(source) => WriterConnectError::MlockError { segment, source },
+                }
+            })?;
 
-        Ok(conn)
+        Ok(connection)
     }
 
     pub(crate) fn id(&self) -> &std::ffi::CStr {
         self.connection_type.id()
     }
 
+    pub(crate) fn name(&self) -> String {
+        self.connection_type.name()
+    }
+
     pub(crate) fn is_stale(&self) -> bool {
-        !self.addr.is_null() && self.queue().done_reading
+        !self.addr.is_null() && self.queue().is_done_reading()
     }
 
-    pub(crate) fn disconnect(&mut self) -> Result<(), WriterDisconnectError> {
+    // Every step is attempted even if an earlier one fails,
+    // all the failures are returned
+    pub(crate) fn disconnect(&mut self) -> Result<(), Vec<WriterDisconnectError>> {
         let addr = self.addr;
         let fd = self.fd;
 
-        if addr.is_null() && fd == 0 {
+        if addr.is_null() && fd == -1 {
             return Ok(());
         }
 
         self.addr = std::ptr::null_mut();
-        self.fd = 0;
+        self.fd = -1;
 
-        munmap(addr, QUEUE_SIZE).map_err(WriterDisconnectError::MunMapError)?;
-        shm_unlink(self.connection_type.id()).map_err(WriterDisconnectError::ShmUnlinkError)?;
+        let unmapped = munmap(addr, Queue::<QUEUE_SIZE>::SIZE).map_err(|source| {
+            WriterDisconnectError::MunMapError {
+                segment: self.connection_type.name(),
+                source,
+            }
+        });
+        let closed = close(fd).map_err(|source| WriterDisconnectError::CloseError {
+            segment: self.connection_type.name(),
+            source,
+        });
+        let unlinked = shm_unlink(self.connection_type.id()).map_err(|source| {
+            WriterDisconnectError::ShmUnlinkError {
+                segment: self.connection_type.name(),
+                source,
+            }
+        });
 
+        let failed: Vec<_> = [unmapped, closed, unlinked]
+            .into_iter()
+            .filter_map(Result::err)
+            .collect();
+        if !failed.is_empty() {
+            return Err(failed);
+        }
+        event!(debug, "writer.segment_released", segment = self.name());
         Ok(())
     }
 
@@ -79,16 +150,51 @@ impl<const QUEUE_SIZE: usize> WriterConnection<QUEUE_SIZE> {
     }
 }
 
+// Sizes a new segment and reserves memory for all of it. ftruncate alone
+// leaves it sparse, and the first write to a page tmpfs has no room for
+// is a SIGBUS instead of an error
+pub(crate) fn allocate(
+    fd: i32,
+    size: usize,
+    connection_type: &ConnectionType,
+) -> Result<(), WriterConnectError> {
+    ftruncate(fd, size as i64).map_err(|source| WriterConnectError::FtruncateError {
+        segment: connection_type.name(),
+        source,
+    })?;
+    if size == 0 {
+        return Ok(());
+    }
+    posix_fallocate(fd, 0, size as i64).map_err(|source| {
+        let segment = connection_type.name();
+        if source.raw_os_error() == Some(libc::ENOSPC) {
+            WriterConnectError::OutOfSpace {
+                segment,
+                size,
+                source,
+            }
+        } else {
+            WriterConnectError::FallocateError { segment, source }
+        }
+    })
+}
+
 impl<const N: usize> Drop for WriterConnection<N> {
     fn drop(&mut self) {
-        println!("[Writer] Disconnecting {:?}", self.id());
-        self.disconnect().unwrap();
+        for err in self.disconnect().err().unwrap_or_default() {
+            event!(
+                warn,
+                "writer.segment_release_failed",
+                segment = self.name(),
+                error = err.to_string(),
+            );
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{ConnectionType, WriterConnection};
+    use crate::{Advice, ConnectionType, Prefault, SegmentOptions, WriterConnection};
 
     #[test]
     fn test_success() {
@@ -102,12 +208,82 @@ mod tests {
         assert_eq!(unsafe { write_ptr.read() }, 42);
     }
 
+    #[test]
+    fn test_segment_options() {
+        for prefault in [Prefault::None, Prefault::Populate, Prefault::Touch] {
+            let options = SegmentOptions {
+                prefault,
+                lock: false,
+                advice: Some(Advice::Sequential),
+            };
+            let connection =
+                WriterConnection::<100_000>::with_options(ConnectionType::random(), &options)
+                    .unwrap();
+            // touched pages are still zeroed
+            assert_eq!(connection.queue().epoch, 0);
+            assert!(connection.queue().messages().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_lock() {
+        let options = SegmentOptions {
+            prefault: Prefault::Touch,
+            lock: true,
+            advice: None,
+        };
+        // well below RLIMIT_MEMLOCK, unless it's been lowered further
+        match WriterConnection::<1_000>::with_options(ConnectionType::random(), &options) {
+            Ok(connection) => assert!(connection.queue().messages().is_empty()),
+            Err(err) => assert!(crate::segment::mlock_refused(&err), "{:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_already_exists() {
+        let connection_type = ConnectionType::random();
+        let connection = WriterConnection::<10>::new(connection_type.clone()).unwrap();
+        connection.queue().epoch = 42;
+
+        // replacing it is up to `Writer`, which owns the names
+        let err = WriterConnection::<10>::new(connection_type.clone()).unwrap_err();
+        assert_eq!(err.operation(), "shm_open");
+        assert_eq!(
+            std::io::Error::from(err).kind(),
+            std::io::ErrorKind::AlreadyExists
+        );
+        let reader = crate::ReaderConnection::<10>::new(connection_type).unwrap();
+        assert_eq!(reader.queue_ref().epoch, 42);
+    }
+
+    #[test]
+    fn test_disconnect_failures() {
+        let connection_type = ConnectionType::random();
+        let mut connection = WriterConnection::<10>::new(connection_type.clone()).unwrap();
+
+        // unmapped and unlinked behind its back
+        crate::capi::munmap(connection.addr, WriterConnection::<10>::SIZE).unwrap();
+        connection.addr = connection.addr.wrapping_byte_add(1);
+        crate::capi::shm_unlink(connection_type.id()).unwrap();
+
+        let failed = connection.disconnect().unwrap_err();
+        let operations: Vec<_> = failed.iter().map(|err| err.operation()).collect();
+        assert_eq!(operations, vec!["munmap", "shm_unlink"]);
+
+        // nothing left to release
+        connection.disconnect().unwrap();
+    }
+
     #[test]
     fn test_invalid_name() {
         let connection_type = ConnectionType::empty();
 
         let err = WriterConnection::<10>::new(connection_type).unwrap_err();
 
-        assert_eq!(format!("{:?}", err), "ShmOpenError(\"Invalid argument\")")
+        assert_eq!(err.to_string(), "shm_open failed for segment \"\"");
+        assert_eq!(
+            std::error::Error::source(&err).unwrap().to_string(),
+            "Invalid argument (os error 22)"
+        );
     }
 }
@@ -1,42 +1,161 @@
-use crate::capi::strerror;
+use std::{error::Error, fmt, io};
 
-#[derive(PartialEq)]
+use crate::writer::ShutdownReport;
+
+#[derive(Debug)]
 pub enum WriterConnectError {
-    ShmOpenError(Option<i32>),
-    FtruncateError(Option<i32>),
-    MmapError(Option<i32>),
+    ShmOpenError {
+        segment: String,
+        source: io::Error,
+    },
+    FtruncateError {
+        segment: String,
+        source: io::Error,
+    },
+    // tmpfs is full, see `shm_space`
+    OutOfSpace {
+        segment: String,
+        size: usize,
+        source: io::Error,
+    },
+    FallocateError {
+        segment: String,
+        source: io::Error,
+    },
+    MmapError {
+        segment: String,
+        source: io::Error,
+    },
+    MadviseError {
+        segment: String,
+        source: io::Error,
+    },
+    MlockError {
+        segment: String,
+        source: io::Error,
+    },
 }
 
-impl std::fmt::Debug for WriterConnectError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let (name, code) = match self {
-            Self::ShmOpenError(code) => ("ShmOpenError", *code),
-            Self::FtruncateError(code) => ("FtruncateError", *code),
-            Self::MmapError(code) => ("MmapError", *code),
-        };
+impl WriterConnectError {
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::ShmOpenError { .. } => "shm_open",
+            Self::FtruncateError { .. } => "ftruncate",
+            Self::OutOfSpace { .. } | Self::FallocateError { .. } => "posix_fallocate",
+            Self::MmapError { .. } => "mmap",
+            Self::MadviseError { .. } => "madvise",
+            Self::MlockError { .. } => "mlock",
+        }
+    }
+
+    pub fn segment(&self) -> &str {
+        match self {
+            Self::ShmOpenError { segment, .. }
+            | Self::FtruncateError { segment, .. }
+            | Self::OutOfSpace { segment, .. }
+            | Self::FallocateError { segment, .. }
+            | Self::MmapError { segment, .. }
+            | Self::MadviseError { segment, .. }
+            | Self::MlockError { segment, .. } => segment,
+        }
+    }
 
-        f.debug_tuple(name)
-            .field(&code.map(strerror).unwrap_or("Unspecified Error"))
-            .finish()
+    pub fn io_error(&self) -> &io::Error {
+        match self {
+            Self::ShmOpenError { source, .. }
+            | Self::FtruncateError { source, .. }
+            | Self::OutOfSpace { source, .. }
+            | Self::FallocateError { source, .. }
+            | Self::MmapError { source, .. }
+            | Self::MadviseError { source, .. }
+            | Self::MlockError { source, .. } => source,
+        }
     }
 }
 
-#[derive(PartialEq)]
+impl fmt::Display for WriterConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfSpace { segment, size, .. } => write!(
+                f,
+                "no space left for {} bytes of segment {:?}",
+                size, segment
+            ),
+            _ => write!(
+                f,
+                "{} failed for segment {:?}",
+                self.operation(),
+                self.segment()
+            ),
+        }
+    }
+}
+
+impl Error for WriterConnectError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.io_error())
+    }
+}
+
+impl From<WriterConnectError> for io::Error {
+    fn from(err: WriterConnectError) -> Self {
+        io::Error::new(err.io_error().kind(), err)
+    }
+}
+
+#[derive(Debug)]
 pub enum WriterDisconnectError {
-    MunMapError(Option<i32>),
-    ShmUnlinkError(Option<i32>),
+    MunMapError { segment: String, source: io::Error },
+    CloseError { segment: String, source: io::Error },
+    ShmUnlinkError { segment: String, source: io::Error },
 }
 
-impl std::fmt::Debug for WriterDisconnectError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let (name, code) = match self {
-            Self::MunMapError(code) => ("MunMapError", *code),
-            Self::ShmUnlinkError(code) => ("ShmUnlinkError", *code),
-        };
+impl WriterDisconnectError {
+    pub fn operation(&self) -> &'static str {
+        match self {
+            Self::MunMapError { .. } => "munmap",
+            Self::CloseError { .. } => "close",
+            Self::ShmUnlinkError { .. } => "shm_unlink",
+        }
+    }
+
+    pub fn segment(&self) -> &str {
+        match self {
+            Self::MunMapError { segment, .. }
+            | Self::CloseError { segment, .. }
+            | Self::ShmUnlinkError { segment, .. } => segment,
+        }
+    }
 
-        f.debug_tuple(name)
-            .field(&code.map(strerror).unwrap_or("Unspecified Error"))
-            .finish()
+    pub fn io_error(&self) -> &io::Error {
+        match self {
+            Self::MunMapError { source, .. }
+            | Self::CloseError { source, .. }
+            | Self::ShmUnlinkError { source, .. } => source,
+        }
+    }
+}
+
+impl fmt::Display for WriterDisconnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} failed for segment {:?}",
+            self.operation(),
+            self.segment()
+        )
+    }
+}
+
+impl Error for WriterDisconnectError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.io_error())
+    }
+}
+
+impl From<WriterDisconnectError> for io::Error {
+    fn from(err: WriterDisconnectError) -> Self {
+        io::Error::new(err.io_error().kind(), err)
     }
 }
 
@@ -44,6 +163,61 @@ impl std::fmt::Debug for WriterDisconnectError {
 pub enum WriterError {
     ConnectError(WriterConnectError),
     DisconnectError(WriterDisconnectError),
+    RootQueueFull,
+    MessageTooLarge { len: usize, max: usize },
+    ShutdownError(ShutdownReport),
+    // another writer is still alive on the same prefix
+    ChannelInUse { prefix: String, pid: u32 },
+}
+
+impl fmt::Display for WriterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ConnectError(_) => f.write_str("failed to connect to a segment"),
+            Self::DisconnectError(_) => f.write_str("failed to disconnect from a segment"),
+            Self::RootQueueFull => {
+                f.write_str("no room to announce a new queue in the root segment")
+            }
+            Self::MessageTooLarge { len, max } => {
+                write!(f, "message of {} bytes is larger than {} bytes", len, max)
+            }
+            Self::ShutdownError(report) => {
+                write!(f, "failed to release {} segment(s)", report.failed.len())
+            }
+            Self::ChannelInUse { prefix, pid } => {
+                write!(f, "{:?} already has a writer (pid {})", prefix, pid)
+            }
+        }
+    }
+}
+
+impl Error for WriterError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::ConnectError(err) => Some(err),
+            Self::DisconnectError(err) => Some(err),
+            Self::RootQueueFull => None,
+            Self::MessageTooLarge { .. } | Self::ChannelInUse { .. } => None,
+            Self::ShutdownError(report) => report
+                .failed
+                .first()
+                .map(|err| err as &(dyn Error + 'static)),
+        }
+    }
+}
+
+impl From<WriterError> for io::Error {
+    fn from(err: WriterError) -> Self {
+        let kind = match &err {
+            WriterError::ConnectError(err) => err.io_error().kind(),
+            WriterError::DisconnectError(err) => err.io_error().kind(),
+            WriterError::RootQueueFull => io::ErrorKind::WouldBlock,
+            WriterError::MessageTooLarge { .. } => io::ErrorKind::InvalidInput,
+            WriterError::ShutdownError(_) => io::ErrorKind::Other,
+            WriterError::ChannelInUse { .. } => io::ErrorKind::AddrInUse,
+        };
+        io::Error::new(kind, err)
+    }
 }
 
 impl From<WriterConnectError> for WriterError {
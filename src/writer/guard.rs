@@ -0,0 +1,59 @@
+use std::ops::{Deref, DerefMut};
+
+use crate::{Writer, WriterError};
+
+// A message being written straight into the shared segment.
+// It's only published on `commit`, dropping the guard abandons it
+// and leaves nothing behind for the reader to see.
+// If the current queue has no room for it, it's written aside instead
+// and the writer only moves on to the next queue once it's committed,
+// at the cost of copying it there (see `Writer::reserve`)
+pub struct WriteGuard<'a, const QUEUE_SIZE: usize> {
+    writer: &'a mut Writer<QUEUE_SIZE>,
+    len: usize,
+    rotate: bool,
+}
+
+impl<'a, const QUEUE_SIZE: usize> WriteGuard<'a, QUEUE_SIZE> {
+    pub(crate) fn new(writer: &'a mut Writer<QUEUE_SIZE>, len: usize, rotate: bool) -> Self {
+        Self {
+            writer,
+            len,
+            rotate,
+        }
+    }
+
+    pub fn commit(self) -> Result<(), WriterError> {
+        if self.rotate {
+            self.writer.rotate()?;
+            self.writer
+                .current_queue()
+                .reserve(self.len)
+                .copy_from_slice(&self.writer.scratch[..self.len]);
+        }
+        self.writer.commit(self.len);
+        Ok(())
+    }
+}
+
+impl<const QUEUE_SIZE: usize> Deref for WriteGuard<'_, QUEUE_SIZE> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if self.rotate {
+            &self.writer.scratch[..self.len]
+        } else {
+            self.writer.current_queue().reserve(self.len)
+        }
+    }
+}
+
+impl<const QUEUE_SIZE: usize> DerefMut for WriteGuard<'_, QUEUE_SIZE> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        if self.rotate {
+            &mut self.writer.scratch[..self.len]
+        } else {
+            self.writer.current_queue().reserve(self.len)
+        }
+    }
+}
@@ -1,38 +1,170 @@
 mod connection;
+pub(crate) use connection::allocate;
 pub use connection::WriterConnection;
 
 mod error;
 pub use error::{WriterConnectError, WriterDisconnectError, WriterError};
 
+mod options;
+pub use options::{Layout, WriterOptions};
+
+mod guard;
+pub use guard::WriteGuard;
+
 mod queue;
 
-use crate::ConnectionType;
+mod shutdown;
+pub use shutdown::ShutdownReport;
+
+use std::io::IoSlice;
+
+use crate::{
+    blob,
+    capi::{process_exists, shm_unlink},
+    event::event,
+    frame::{self, Kind},
+    ConnectionType, ReaderConnection, Stats,
+};
 
 pub struct Writer<const QUEUE_SIZE: usize> {
     root_connection: WriterConnection<1_000>,
     connections: Vec<WriterConnection<QUEUE_SIZE>>,
+    pool: Vec<WriterConnection<QUEUE_SIZE>>,
+    epoch: u64,
+    sequence: u64,
+    // announced in the queue of that epoch, but maybe not picked up yet
+    blobs: Vec<(u64, ConnectionType)>,
     prefix: String,
+    options: WriterOptions,
+    // everything is released already, there's nothing left for `Drop`
+    shut_down: bool,
+    // where a `WriteGuard` writes while the next queue isn't there yet
+    scratch: Vec<u8>,
 }
 
 impl<const QUEUE_SIZE: usize> Writer<QUEUE_SIZE> {
     pub fn new(prefix: impl Into<String>) -> Result<Self, WriterError> {
-        let prefix: String = prefix.into();
+        Self::with_options(prefix, WriterOptions::default())
+    }
+
+    pub fn with_options(
+        prefix: impl Into<String>,
+        options: WriterOptions,
+    ) -> Result<Self, WriterError> {
+        Self::create(prefix.into(), options, 0)
+    }
+
+    // The fingerprint is in place before the first queue is announced,
+    // so no reader can connect without seeing it
+    pub(crate) fn create(
+        prefix: String,
+        options: WriterOptions,
+        type_fingerprint: u64,
+    ) -> Result<Self, WriterError> {
+        // the root segment is replaced below, which would cut off
+        // the reader of a writer that is still there
+        if let Some(pid) = live_writer(&prefix) {
+            return Err(WriterError::ChannelInUse { prefix, pid });
+        }
+        unlink_stale(&ConnectionType::root(&prefix));
 
         let root_connection = WriterConnection::new(ConnectionType::root(&prefix))?;
+        root_connection.queue().generation = new_generation();
+        root_connection
+            .queue()
+            .channel
+            .set_writer_pid(std::process::id());
+        root_connection
+            .queue()
+            .channel
+            .set_type_fingerprint(type_fingerprint);
+
         let mut writer = Self {
             root_connection,
             connections: vec![],
+            pool: vec![],
+            epoch: 0,
+            sequence: 0,
+            blobs: vec![],
             prefix,
+            options,
+            shut_down: false,
+            scratch: vec![],
         };
         writer.provision_new_queue_connection()?;
 
         Ok(writer)
     }
 
+    pub fn generation(&self) -> u64 {
+        self.root_connection.queue().generation
+    }
+
+    // Releases every segment, unlike `Drop` failures are reported back
+    pub fn shutdown(mut self) -> Result<ShutdownReport, WriterError> {
+        let report = self.release_all();
+        self.shut_down = true;
+        if report.is_clean() {
+            Ok(report)
+        } else {
+            Err(WriterError::ShutdownError(report))
+        }
+    }
+
+    fn release_all(&mut self) -> ShutdownReport {
+        let mut report = ShutdownReport::default();
+
+        let result = self.root_connection.disconnect();
+        report.record(self.root_connection.name(), result);
+
+        for conn in self.connections.iter_mut().chain(self.pool.iter_mut()) {
+            let result = conn.disconnect();
+            report.record(conn.name(), result);
+        }
+
+        for (_, blob) in self.blobs.drain(..) {
+            match blob::unlink(&blob) {
+                Ok(true) => report.record(blob.name(), Ok(())),
+                Ok(false) => {}
+                Err(source) => report.record(
+                    blob.name(),
+                    Err(vec![WriterDisconnectError::ShmUnlinkError {
+                        segment: blob.name(),
+                        source,
+                    }]),
+                ),
+            }
+        }
+
+        report
+    }
+
     pub(crate) fn cleanup(&mut self) -> Result<(), WriterError> {
-        for connection in &mut self.connections {
-            if connection.is_stale() {
-                connection.disconnect()?;
+        let mut i = 0;
+        while i < self.connections.len() {
+            if !self.connections[i].is_stale() {
+                i += 1;
+                continue;
+            }
+
+            let mut connection = self.connections.remove(i);
+            // the reader has popped them, so they're its to unlink
+            let epoch = connection.queue().epoch;
+            self.blobs.retain(|(blob_epoch, _)| *blob_epoch != epoch);
+            if self.pool.len() < self.options.pool_size {
+                self.pool.push(connection);
+            } else if let Err(failed) = connection.disconnect() {
+                let mut failed = failed.into_iter();
+                let first = failed.next().unwrap();
+                for err in failed {
+                    event!(
+                        warn,
+                        "writer.segment_release_failed",
+                        segment = err.segment(),
+                        error = err.to_string(),
+                    );
+                }
+                return Err(first.into());
             }
         }
 
@@ -42,42 +174,337 @@ impl<const QUEUE_SIZE: usize> Writer<QUEUE_SIZE> {
     pub(crate) fn provision_new_queue_connection(&mut self) -> Result<(), WriterError> {
         self.cleanup()?;
 
-        let connection =
-            WriterConnection::new(ConnectionType::worker(self.connections.len(), &self.prefix))?;
+        let epoch = self.epoch;
+        self.epoch += 1;
+
+        let connection = match self.pool.pop() {
+            Some(connection) => {
+                event!(
+                    debug,
+                    "writer.segment_recycled",
+                    segment = connection.name(),
+                    epoch = epoch,
+                );
+                connection
+            }
+            None => {
+                let connection_type = ConnectionType::worker(epoch as usize, &self.prefix);
+                unlink_stale(&connection_type);
+                WriterConnection::with_options(connection_type, &self.options.segments)?
+            }
+        };
+        connection
+            .queue()
+            .reset(self.generation(), epoch, self.frame_flags());
 
         self.connections.push(connection);
-        self.notify_about_new_queue();
+        if let Err(err) = self.notify_about_new_queue(epoch) {
+            // nobody is going to read from it
+            self.connections.pop();
+            return Err(err);
+        }
+        self.root_connection
+            .queue()
+            .channel
+            .record_writer_segment(epoch);
 
         Ok(())
     }
 
-    fn notify_about_new_queue(&mut self) {
+    fn frame_flags(&self) -> u64 {
+        let mut flags = match self.options.layout {
+            Layout::Packed => 0,
+            Layout::Aligned8 => frame::PADDED | frame::ALIGNED_8,
+            Layout::Aligned64 => frame::PADDED | frame::ALIGNED_64,
+        };
+        if self.options.timestamps {
+            flags |= frame::TIMESTAMPED;
+        }
+        flags
+    }
+
+    fn notify_about_new_queue(&mut self, epoch: u64) -> Result<(), WriterError> {
         let new_conn_id = self.connections.last().unwrap().id();
-        println!("[Writer] Notifying about new queue {:?}", new_conn_id);
-        self.root_connection.queue().push(new_conn_id.to_bytes())
+        event!(
+            debug,
+            "writer.queue_announced",
+            segment = new_conn_id,
+            epoch = epoch,
+        );
+        if self
+            .root_connection
+            .queue()
+            .push_wrapping(&announcement(epoch, new_conn_id))
+        {
+            Ok(())
+        } else {
+            Err(WriterError::RootQueueFull)
+        }
     }
 
+    // Messages longer than `max_message_len` are split into fragments,
+    // which the reader puts back together
     pub fn ipc_push(&mut self, message: &[u8]) -> Result<(), WriterError> {
-        let mut current_queue = self.connections.last().unwrap().queue();
+        if message.len() > self.max_message_len() {
+            return self.push_fragmented(message);
+        }
+
+        let mut guard = self.reserve(message.len())?;
+        guard.copy_from_slice(message);
+        guard.commit()
+    }
+
+    // All the parts are written back to back as a single message
+    pub fn push_vectored(&mut self, parts: &[IoSlice<'_>]) -> Result<(), WriterError> {
+        let len = parts.iter().map(|part| part.len()).sum();
+        if len > self.max_message_len() {
+            let message: Vec<u8> = parts.iter().flat_map(|part| part.iter().copied()).collect();
+            return self.push_fragmented(&message);
+        }
 
-        if !current_queue.can_push(message) {
-            self.provision_new_queue_connection()?;
-            current_queue.done_writing = true;
-            current_queue = self.connections.last().unwrap().queue();
+        let mut guard = self.reserve(len)?;
+        let mut at = 0;
+        for part in parts {
+            guard[at..at + part.len()].copy_from_slice(part);
+            at += part.len();
         }
+        guard.commit()
+    }
 
-        current_queue.push(message);
+    // Every run of messages that fits in the current queue is published with
+    // a single cursor update, the rest goes on to the next queue(s) the same way.
+    // Messages longer than `max_message_len` are fragmented as usual
+    pub fn push_batch(&mut self, messages: &[&[u8]]) -> Result<(), WriterError> {
+        let max = self.max_message_len();
+
+        let mut rest = messages;
+        loop {
+            let current_queue = self.current_queue();
+            let fitting = rest
+                .iter()
+                .position(|message| message.len() > max)
+                .unwrap_or(rest.len());
+            let pushed = current_queue.fitting(&rest[..fitting]);
+            let bytes = rest[..pushed].iter().map(|message| message.len()).sum();
+            self.root_connection
+                .queue()
+                .channel
+                .record_pushes(pushed, bytes);
+            current_queue.push_all(&rest[..pushed], self.sequence);
+            self.sequence += pushed as u64;
+            rest = &rest[pushed..];
+
+            match rest.first() {
+                None => return Ok(()),
+                Some(message) if message.len() > max => {
+                    self.push_fragmented(message)?;
+                    rest = &rest[1..];
+                }
+                Some(_) => self.rotate()?,
+            }
+        }
+    }
+
+    // The payload goes in a segment of its own, which the reader maps
+    // as it is, only a small descriptor of it goes through the queue.
+    // Meant for the occasional payload that would take many fragments
+    pub fn push_large(&mut self, message: &[u8]) -> Result<(), WriterError> {
+        let max = self.max_message_len();
+        if max < blob::DESCRIPTOR_LEN {
+            return Err(WriterError::MessageTooLarge {
+                len: message.len(),
+                max,
+            });
+        }
 
+        let connection_type = ConnectionType::blob(self.generation(), self.sequence, &self.prefix);
+        let descriptor = blob::create(&connection_type, message)?;
+
+        if !self.current_queue().can_push(blob::DESCRIPTOR_LEN) {
+            if let Err(err) = self.rotate() {
+                let _ = blob::unlink(&connection_type);
+                return Err(err);
+            }
+        }
+        self.blobs.push((self.epoch - 1, connection_type));
+
+        self.root_connection
+            .queue()
+            .channel
+            .record_pushes(1, message.len());
+        let queue = self.current_queue();
+        queue
+            .reserve(blob::DESCRIPTOR_LEN)
+            .copy_from_slice(&descriptor.encode());
+        queue.commit(blob::DESCRIPTOR_LEN, Kind::Blob, self.sequence);
+        self.sequence += 1;
         Ok(())
     }
+
+    // Room for a message right in the shared segment, see `WriteGuard`.
+    // It can't be fragmented, so it's limited to `max_message_len`.
+    // If it doesn't fit in the current queue it's not zero-copy: it's
+    // written to a buffer of the writer's and copied over on `commit`,
+    // once the next queue is there. That's once per rotation, and keeps
+    // an abandoned guard from rotating for nothing
+    pub fn reserve(&mut self, len: usize) -> Result<WriteGuard<'_, QUEUE_SIZE>, WriterError> {
+        let max = self.max_message_len();
+        if len > max {
+            return Err(WriterError::MessageTooLarge { len, max });
+        }
+
+        // rotating is up to `commit`, an abandoned guard changes nothing
+        let rotate = !self.current_queue().can_push(len);
+        if rotate && self.scratch.len() < len {
+            self.scratch.resize(len, 0);
+        }
+
+        Ok(WriteGuard::new(self, len, rotate))
+    }
+
+    // The longest message that goes in a single frame, the length
+    // has to fit both the frame header and an empty queue
+    pub fn max_message_len(&self) -> usize {
+        let max = QUEUE_SIZE.saturating_sub(frame::payload_at(0, self.frame_flags()));
+        max.min(u8::MAX as usize)
+    }
+
+    // Every fragment takes as much of the current queue as it can,
+    // so a large message fills the segments it's spread across.
+    // If the writer fails (or dies) half way, the reader drops what it's got
+    // once it sees the next message (or a new writer)
+    fn push_fragmented(&mut self, message: &[u8]) -> Result<(), WriterError> {
+        let max = self.max_message_len();
+        if max <= frame::FRAGMENTED_LEN {
+            return Err(WriterError::MessageTooLarge {
+                len: message.len(),
+                max,
+            });
+        }
+
+        let sequence = self.sequence;
+        self.sequence += 1;
+        self.root_connection
+            .queue()
+            .channel
+            .record_pushes(1, message.len());
+
+        let mut rest = message;
+        let mut first = true;
+        while first || !rest.is_empty() {
+            let prefix = if first { frame::FRAGMENTED_LEN } else { 0 };
+            let room = self.current_queue().room().min(max);
+            if room <= prefix {
+                self.rotate()?;
+                continue;
+            }
+
+            let len = rest.len().min(room - prefix);
+            let kind = match (first, len == rest.len()) {
+                (true, _) => Kind::First,
+                (false, false) => Kind::Middle,
+                (false, true) => Kind::Last,
+            };
+
+            let queue = self.current_queue();
+            let payload = queue.reserve(prefix + len);
+            if first {
+                payload[..prefix].copy_from_slice(&(message.len() as u64).to_le_bytes());
+            }
+            payload[prefix..].copy_from_slice(&rest[..len]);
+            queue.commit(prefix + len, kind, sequence);
+
+            rest = &rest[len..];
+            first = false;
+        }
+
+        Ok(())
+    }
+
+    // Moves on to a new queue, the current one is over
+    fn rotate(&mut self) -> Result<(), WriterError> {
+        let current_queue = self.current_queue();
+        self.provision_new_queue_connection()?;
+        current_queue.mark_done_writing();
+        Ok(())
+    }
+
+    fn current_queue(&self) -> &'static mut queue::Queue<QUEUE_SIZE> {
+        self.connections.last().unwrap().queue()
+    }
+
+    fn commit(&mut self, len: usize) {
+        self.root_connection.queue().channel.record_pushes(1, len);
+        self.current_queue().commit(len, Kind::Whole, self.sequence);
+        self.sequence += 1;
+    }
+
+    // What a crashed writer leaves behind: all of its segments,
+    // and the pid of a process that is gone
+    #[cfg(test)]
+    pub(crate) fn crash(self) {
+        self.root_connection
+            .queue()
+            .channel
+            .set_writer_pid(i32::MAX as u32);
+        std::mem::forget(self);
+    }
+
+    pub fn stats(&self) -> Stats {
+        let segments = self.connections.len() + self.pool.len();
+        self.root_connection.queue().channel.snapshot(
+            segments + 1,
+            WriterConnection::<1_000>::SIZE + segments * WriterConnection::<QUEUE_SIZE>::SIZE,
+        )
+    }
+}
+
+// Segment name prefixed with the epoch it's been handed out for
+fn announcement(epoch: u64, id: &std::ffi::CStr) -> Vec<u8> {
+    let mut announcement = epoch.to_le_bytes().to_vec();
+    announcement.extend_from_slice(id.to_bytes());
+    announcement
+}
+
+// Pid of the writer the root segment of `prefix` belongs to, if that one is
+// still running. Segments left behind by a crashed writer can be replaced
+fn live_writer(prefix: &str) -> Option<u32> {
+    let root = ReaderConnection::<1_000>::read_only(ConnectionType::root(prefix)).ok()?;
+    let pid = root.queue_ref().channel.writer_pid();
+    (pid != 0 && process_exists(pid)).then_some(pid)
+}
+
+// A segment left behind by a previous writer may still be mapped
+// by readers, so instead of reusing it it's replaced with a new one
+fn unlink_stale(connection_type: &ConnectionType) {
+    let _ = shm_unlink(connection_type.id());
+}
+
+// Every writer incarnation gets its own generation, so readers can
+// tell a restarted writer apart from the one they were attached to.
+// Wall clock is used (instead of a counter) because a writer that has
+// shut down cleanly leaves nothing behind to count from.
+fn new_generation() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or(1)
 }
 
 impl<const QUEUE_SIZE: usize> Drop for Writer<QUEUE_SIZE> {
     fn drop(&mut self) {
-        self.root_connection.disconnect().unwrap();
-
-        for conn in &mut self.connections {
-            conn.disconnect().unwrap()
+        if self.shut_down {
+            return;
+        }
+        let report = self.release_all();
+
+        for err in report.failed {
+            event!(
+                warn,
+                "writer.segment_release_failed",
+                segment = err.segment(),
+                error = err.to_string(),
+            );
         }
     }
 }
@@ -85,7 +512,8 @@ impl<const QUEUE_SIZE: usize> Drop for Writer<QUEUE_SIZE> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    const QUEUE_SIZE: usize = 20;
+    use std::sync::atomic::Ordering;
+    const QUEUE_SIZE: usize = 38;
 
     #[test]
     fn test_queue_provisioning() {
@@ -99,11 +527,13 @@ mod tests {
         writer.ipc_push(b"444444444").unwrap();
 
         let root_queue = writer.root_connection.queue();
+        let worker0 = ConnectionType::worker(0, &prefix);
+        let worker1 = ConnectionType::worker(1, &prefix);
         assert_eq!(
-            root_queue.messages(),
+            root_queue.pending_wrapping(),
             vec![
-                format!("/{}-worker-0", prefix),
-                format!("/{}-worker-1", prefix)
+                String::from_utf8(announcement(0, worker0.id())).unwrap(),
+                String::from_utf8(announcement(1, worker1.id())).unwrap(),
             ]
         );
 
@@ -118,7 +548,11 @@ mod tests {
     fn test_cleanup() {
         let prefix = crate::random_name();
 
-        let mut writer = Writer::<QUEUE_SIZE>::new(&prefix).unwrap();
+        let options = WriterOptions {
+            pool_size: 0,
+            ..WriterOptions::default()
+        };
+        let mut writer = Writer::<QUEUE_SIZE>::with_options(&prefix, options).unwrap();
 
         writer.ipc_push(b"111111111").unwrap();
         writer.ipc_push(b"222222222").unwrap();
@@ -127,15 +561,317 @@ mod tests {
 
         // manually mark writer queues as stale
         for conn in &mut writer.connections {
-            conn.queue().done_reading = true;
+            conn.queue().done_reading.store(true, Ordering::Relaxed);
         }
 
         writer.cleanup().unwrap();
 
-        assert_eq!(writer.connections[0].addr, std::ptr::null_mut());
-        assert_eq!(writer.connections[1].addr, std::ptr::null_mut());
+        assert!(writer.connections.is_empty());
 
         // check how they are ignored on the next cleanup
         writer.cleanup().unwrap();
     }
+
+    #[test]
+    fn test_pooling() {
+        let prefix = crate::random_name();
+
+        let options = WriterOptions {
+            pool_size: 1,
+            ..WriterOptions::default()
+        };
+        let mut writer = Writer::<QUEUE_SIZE>::with_options(&prefix, options).unwrap();
+
+        writer.ipc_push(b"111111111").unwrap();
+        writer.ipc_push(b"222222222").unwrap();
+        writer.ipc_push(b"333333333").unwrap();
+        writer.ipc_push(b"444444444").unwrap();
+        writer.ipc_push(b"555555555").unwrap();
+        writer.ipc_push(b"666666666").unwrap();
+
+        // nothing is consumed, so every queue is a new one
+        assert_eq!(writer.connections.len(), 3);
+        assert!(writer.pool.is_empty());
+
+        // manually mark consumed queues as stale
+        writer.connections[0]
+            .queue()
+            .done_reading
+            .store(true, Ordering::Relaxed);
+        writer.connections[1]
+            .queue()
+            .done_reading
+            .store(true, Ordering::Relaxed);
+        writer.cleanup().unwrap();
+
+        // one is kept for reuse, the other one is released
+        assert_eq!(writer.pool.len(), 1);
+        assert_eq!(writer.pool[0].id(), ConnectionType::worker(0, &prefix).id());
+        assert_eq!(writer.connections.len(), 1);
+
+        writer.ipc_push(b"777777777").unwrap();
+
+        let current = writer.connections.last().unwrap();
+        assert_eq!(current.id(), ConnectionType::worker(0, &prefix).id());
+        assert_eq!(current.queue().epoch, 3);
+        assert_eq!(current.queue().messages(), vec!["777777777"]);
+        assert!(writer.pool.is_empty());
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<QUEUE_SIZE>::new(&prefix).unwrap();
+        writer.ipc_push(b"111111111").unwrap();
+        writer.ipc_push(b"222222222").unwrap();
+        writer.ipc_push(b"333333333").unwrap();
+
+        let report = writer.shutdown().unwrap();
+        assert_eq!(
+            report.released,
+            vec![
+                format!("/{}-root", prefix),
+                format!("/{}-worker-0", prefix),
+                format!("/{}-worker-1", prefix),
+            ]
+        );
+        assert!(report.failed.is_empty());
+    }
+
+    #[test]
+    fn test_shutdown_unread_blob() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<QUEUE_SIZE>::new(&prefix).unwrap();
+        writer.push_large(&[1; 1_000]).unwrap();
+        assert_eq!(writer.stats().messages_pushed, 1);
+        assert_eq!(writer.stats().bytes_pushed, 1_000);
+
+        let blob = format!("/{}-blob-{}-0", prefix, writer.generation());
+        let report = writer.shutdown().unwrap();
+        assert_eq!(
+            report.released,
+            vec![
+                format!("/{}-root", prefix),
+                format!("/{}-worker-0", prefix),
+                blob,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shutdown_unlinked_segment() {
+        let prefix = crate::random_name();
+
+        let writer = Writer::<QUEUE_SIZE>::new(&prefix).unwrap();
+        // somebody else has removed it already
+        crate::capi::shm_unlink(ConnectionType::worker(0, &prefix).id()).unwrap();
+
+        let report = match writer.shutdown() {
+            Err(WriterError::ShutdownError(report)) => report,
+            other => panic!("expected a shutdown error, got {:?}", other),
+        };
+        assert_eq!(report.released, vec![format!("/{}-root", prefix)]);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].segment(), format!("/{}-worker-0", prefix));
+        assert_eq!(report.failed[0].operation(), "shm_unlink");
+        assert_eq!(
+            report.failed[0].io_error().kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_channel_in_use() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<QUEUE_SIZE>::new(&prefix).unwrap();
+        let err = Writer::<QUEUE_SIZE>::new(&prefix).err().unwrap();
+        assert!(matches!(
+            &err,
+            WriterError::ChannelInUse { prefix: p, pid } if *p == prefix && *pid == std::process::id()
+        ));
+        assert_eq!(
+            std::io::Error::from(err).kind(),
+            std::io::ErrorKind::AddrInUse
+        );
+
+        // the first one is left alone
+        writer.ipc_push(b"111111111").unwrap();
+        assert_eq!(writer.current_queue().messages(), vec!["111111111"]);
+
+        // once it's gone, whatever it has left behind is taken over
+        writer.crash();
+        Writer::<QUEUE_SIZE>::new(&prefix).unwrap();
+    }
+
+    #[test]
+    fn test_drop_unlinked_segment() {
+        let prefix = crate::random_name();
+
+        let writer = Writer::<QUEUE_SIZE>::new(&prefix).unwrap();
+        crate::capi::shm_unlink(ConnectionType::root(&prefix).id()).unwrap();
+
+        // must not panic
+        drop(writer);
+    }
+
+    #[test]
+    fn test_reserve() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<QUEUE_SIZE>::new(&prefix).unwrap();
+
+        let mut guard = writer.reserve(9).unwrap();
+        guard.copy_from_slice(b"111111111");
+        guard.commit().unwrap();
+
+        // abandoned, nothing is published
+        {
+            let mut guard = writer.reserve(9).unwrap();
+            guard.copy_from_slice(b"xxxxxxxxx");
+        }
+        assert_eq!(writer.current_queue().messages(), vec!["111111111"]);
+        assert_eq!(writer.stats().messages_pushed, 1);
+
+        let mut guard = writer.reserve(3).unwrap();
+        guard[..2].copy_from_slice(b"22");
+        guard[2] = b'2';
+        guard.commit().unwrap();
+        assert_eq!(writer.current_queue().messages(), vec!["111111111", "222"]);
+
+        // doesn't fit, but nothing changes until it's committed
+        {
+            let mut guard = writer.reserve(9).unwrap();
+            guard.copy_from_slice(b"xxxxxxxxx");
+        }
+        assert_eq!(writer.connections.len(), 1);
+        assert_eq!(writer.stats().rotations, 0);
+        assert_eq!(writer.root_connection.queue().pending_wrapping().len(), 1);
+        assert!(!writer.current_queue().done_writing.load(Ordering::Relaxed));
+
+        let mut guard = writer.reserve(9).unwrap();
+        guard.copy_from_slice(b"333333333");
+        guard.commit().unwrap();
+        assert_eq!(writer.connections.len(), 2);
+        assert_eq!(writer.stats().rotations, 1);
+        assert_eq!(writer.current_queue().messages(), vec!["333333333"]);
+    }
+
+    #[test]
+    fn test_push_batch() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<QUEUE_SIZE>::new(&prefix).unwrap();
+        writer.ipc_push(b"111111111").unwrap();
+        writer
+            .push_batch(&[b"222222222", b"333333333", b"444444444", b"5"])
+            .unwrap();
+        writer.push_batch(&[]).unwrap();
+
+        let messages: Vec<_> = writer
+            .connections
+            .iter()
+            .map(|connection| connection.queue().messages())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                vec!["111111111", "222222222"],
+                vec!["333333333", "444444444"],
+                vec!["5"],
+            ]
+        );
+        assert_eq!(writer.stats().messages_pushed, 5);
+        assert_eq!(writer.stats().bytes_pushed, 37);
+
+        // the one that doesn't fit in a queue is fragmented
+        writer.push_batch(&[b"6", &[b'7'; 40], b"8"]).unwrap();
+        assert_eq!(writer.stats().messages_pushed, 8);
+        assert_eq!(writer.stats().bytes_pushed, 79);
+    }
+
+    #[test]
+    fn test_push_vectored() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<QUEUE_SIZE>::new(&prefix).unwrap();
+        writer
+            .push_vectored(&[
+                IoSlice::new(b"1111"),
+                IoSlice::new(b""),
+                IoSlice::new(b"11111"),
+            ])
+            .unwrap();
+        // doesn't fit as a whole
+        writer
+            .push_vectored(&[IoSlice::new(b"22222"), IoSlice::new(b"22222")])
+            .unwrap();
+        writer.push_vectored(&[]).unwrap();
+
+        assert_eq!(writer.connections[0].queue().messages(), vec!["111111111"]);
+        assert_eq!(
+            writer.connections[1].queue().messages(),
+            vec!["2222222222", ""]
+        );
+        assert_eq!(writer.stats().messages_pushed, 3);
+
+        // larger than a queue
+        writer
+            .push_vectored(&[IoSlice::new(&[0; 20]), IoSlice::new(&[0; 20])])
+            .unwrap();
+        assert_eq!(writer.stats().messages_pushed, 4);
+    }
+
+    #[test]
+    fn test_message_too_large() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<QUEUE_SIZE>::new(&prefix).unwrap();
+        assert_eq!(writer.max_message_len(), QUEUE_SIZE - 10);
+
+        // can't be reserved in place, but can be pushed in fragments
+        let err = writer.reserve(QUEUE_SIZE - 9).err().unwrap();
+        assert!(matches!(err, WriterError::MessageTooLarge { len, max } if len == 29 && max == 28));
+        writer.ipc_push(&[0; QUEUE_SIZE - 9]).unwrap();
+
+        let mut writer = Writer::<1_000>::new(crate::random_name()).unwrap();
+        assert_eq!(writer.max_message_len(), 255);
+        writer.ipc_push(&[0; 256]).unwrap();
+
+        // no room for a fragment after the length of the message
+        let mut writer = Writer::<18>::new(crate::random_name()).unwrap();
+        assert_eq!(writer.max_message_len(), 8);
+        writer.ipc_push(&[0; 8]).unwrap();
+        assert!(writer.ipc_push(&[0; 9]).is_err());
+
+        // the payload starts on a cache line
+        let options = WriterOptions {
+            layout: Layout::Aligned64,
+            ..WriterOptions::default()
+        };
+        let writer = Writer::<100>::with_options(crate::random_name(), options).unwrap();
+        assert_eq!(writer.max_message_len(), 36);
+    }
+
+    #[test]
+    fn test_root_queue_full() {
+        let prefix = crate::random_name();
+
+        let mut writer = Writer::<QUEUE_SIZE>::new(&prefix).unwrap();
+
+        // nobody reads announcements, so eventually there's no room for a new one
+        let err = loop {
+            if let Err(err) = writer.ipc_push(b"111111111") {
+                break err;
+            }
+        };
+        assert!(matches!(err, WriterError::RootQueueFull));
+
+        // the queue that couldn't be announced is released right away
+        let connections = writer.connections.len();
+        writer.ipc_push(b"111111111").unwrap_err();
+        assert_eq!(writer.connections.len(), connections);
+    }
 }
@@ -0,0 +1,43 @@
+use crate::SegmentOptions;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriterOptions {
+    // How many consumed segments are kept mapped (and already faulted in)
+    // to be handed out again on rotation instead of being unlinked
+    pub pool_size: usize,
+    // Every message carries the CLOCK_MONOTONIC time it was pushed at,
+    // which lets the reader measure end-to-end latency (8 bytes per message)
+    pub timestamps: bool,
+    pub layout: Layout,
+    // how worker segments are mapped
+    pub segments: SegmentOptions,
+}
+
+impl Default for WriterOptions {
+    fn default() -> Self {
+        Self {
+            pool_size: 2,
+            timestamps: false,
+            layout: Layout::default(),
+            segments: SegmentOptions::default(),
+        }
+    }
+}
+
+// How worker segments are laid out, recorded in every one of them,
+// so the reader doesn't need to be told
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    // Both cursors share a cache line, frames are back to back.
+    // The header is the same for every layout though, the padded cursors
+    // the other ones use are there too, only left alone
+    #[default]
+    Packed,
+    // The writer's and the reader's cursor are on cache lines of their own,
+    // so they don't bounce between cores on every push and pop, and every
+    // payload starts on an 8 byte boundary, at the cost of up to 7 bytes
+    // of padding per message
+    Aligned8,
+    // Same, with payloads on a cache line of their own
+    Aligned64,
+}
@@ -1,18 +1,47 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::{
+    capi::monotonic_nanos,
+    channel::ChannelHeader,
+    frame::{self, CachePadded, Kind, PADDED, TIMESTAMPED},
+};
+
+// Messages are only readable up to `end`, so a recycled segment can be
+// handed out again by resetting the header, whatever is left in `data`
+// from the previous use is never looked at.
+// `start` and `end` share a cache line with everything else in the header,
+// with `PADDED` set `consumer` and `producer` are used in their place
+#[repr(C)]
 pub(crate) struct Queue<const N: usize> {
-    start: usize,
-    end: usize,
-    pub(crate) done_reading: bool,
-    pub(crate) done_writing: bool,
+    // of the writer that has set the segment up
+    pub(crate) generation: u64,
+    // the use of a worker segment it's been handed out for, 0 in the root one
+    pub(crate) epoch: u64,
+    // which optional frame fields are written, see `frame`
+    pub(crate) flags: u64,
+    pub(crate) channel: ChannelHeader,
+    start: AtomicUsize,
+    end: AtomicUsize,
+    pub(crate) done_reading: AtomicBool,
+    pub(crate) done_writing: AtomicBool,
+    consumer: CachePadded,
+    producer: CachePadded,
     data: [u8; N],
 }
 
 impl<const N: usize> std::fmt::Debug for Queue<N> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Queue")
+            .field("generation", &self.generation)
+            .field("epoch", &self.epoch)
+            .field("flags", &self.flags)
+            .field("channel", &self.channel)
             .field("start", &self.start)
             .field("end", &self.end)
             .field("done_reading", &self.done_reading)
             .field("done_writing", &self.done_writing)
+            .field("consumer", &self.consumer)
+            .field("producer", &self.producer)
             .field("data", &self.data)
             .field("messages", &self.messages())
             .finish()
@@ -20,44 +49,188 @@ impl<const N: usize> std::fmt::Debug for Queue<N> {
 }
 
 impl<const N: usize> Queue<N> {
+    pub(crate) const SIZE: usize = std::mem::size_of::<Self>();
+
     pub(crate) fn from_ptr(ptr: *mut std::ffi::c_void) -> &'static mut Self {
         let ptr = ptr as *mut Queue<N>;
         unsafe { ptr.as_mut() }.unwrap()
     }
 
-    pub(crate) fn push(&mut self, message: &[u8]) {
-        // write length
-        self.data[self.end] = message.len() as u8;
-        self.end += 1;
+    pub(crate) fn reset(&mut self, generation: u64, epoch: u64, flags: u64) {
+        self.generation = generation;
+        self.epoch = epoch;
+        self.flags = flags;
+        self.start().store(0, Ordering::Relaxed);
+        self.end().store(0, Ordering::Relaxed);
+        self.done_reading.store(false, Ordering::Relaxed);
+        self.done_writing.store(false, Ordering::Release);
+    }
+
+    fn start(&self) -> &AtomicUsize {
+        if self.flags & PADDED != 0 {
+            &self.consumer.0
+        } else {
+            &self.start
+        }
+    }
+
+    fn end(&self) -> &AtomicUsize {
+        if self.flags & PADDED != 0 {
+            &self.producer.0
+        } else {
+            &self.end
+        }
+    }
+
+    // Room for a message of `len` bytes right after the last published one,
+    // nothing in there is visible to the reader until it's committed
+    pub(crate) fn reserve(&mut self, len: usize) -> &mut [u8] {
+        let at = frame::payload_at(self.end().load(Ordering::Relaxed), self.flags);
+        &mut self.data[at..at + len]
+    }
+
+    pub(crate) fn commit(&mut self, len: usize, kind: Kind, sequence: u64) {
+        let end = self.end().load(Ordering::Relaxed);
+        let end = self.write_header(end, len, kind, sequence, self.timestamp());
+        self.end().store(end + len, Ordering::Release);
+    }
+
+    // The largest payload a frame pushed right now can have
+    pub(crate) fn room(&self) -> usize {
+        N.saturating_sub(frame::payload_at(
+            self.end().load(Ordering::Relaxed),
+            self.flags,
+        ))
+    }
+
+    // How many of `messages` there is room for
+    pub(crate) fn fitting(&self, messages: &[&[u8]]) -> usize {
+        let mut end = self.end().load(Ordering::Relaxed);
+        let mut fitting = 0;
+        for message in messages {
+            end = frame::payload_at(end, self.flags) + message.len();
+            if end > N {
+                break;
+            }
+            fitting += 1;
+        }
+        fitting
+    }
+
+    // Publishes all of `messages` at once, they must fit (see `fitting`)
+    pub(crate) fn push_all(&mut self, messages: &[&[u8]], first_sequence: u64) {
+        let timestamp = self.timestamp();
+        let mut end = self.end().load(Ordering::Relaxed);
+
+        for (message, sequence) in messages.iter().zip(first_sequence..) {
+            end = self.write_header(end, message.len(), Kind::Whole, sequence, timestamp);
+            self.data[end..end + message.len()].copy_from_slice(message);
+            end += message.len();
+        }
+
+        self.end().store(end, Ordering::Release);
+    }
+
+    fn timestamp(&self) -> u64 {
+        if self.flags & TIMESTAMPED != 0 {
+            monotonic_nanos()
+        } else {
+            0
+        }
+    }
 
-        // write content
-        self.data[self.end..self.end + message.len()].clone_from_slice(message);
-        self.end += message.len();
+    // Writes length and whatever else the segment is configured with
+    // in the frame after `at`, returns where the payload goes
+    fn write_header(
+        &mut self,
+        at: usize,
+        len: usize,
+        kind: Kind,
+        sequence: u64,
+        timestamp: u64,
+    ) -> usize {
+        let at = frame::next_frame(at, self.flags);
+        at + frame::encode_header(
+            &mut self.data[at..],
+            self.flags,
+            len,
+            kind,
+            sequence,
+            timestamp,
+        )
     }
 
-    pub(crate) fn can_push(&mut self, message: &[u8]) -> bool {
-        let left = N - self.end;
-        left >= message.len() + 1
+    // The root queue is written for as long as the writer lives, so unlike
+    // worker queues it wraps around: `start` and `end` keep growing and are
+    // taken modulo N, a zero length means "continue from the beginning".
+    // Returns false if the reader hasn't made enough room yet.
+    pub(crate) fn push_wrapping(&mut self, message: &[u8]) -> bool {
+        let start = self.start().load(Ordering::Acquire);
+        let mut end = self.end().load(Ordering::Relaxed);
+
+        let required = message.len() + 1;
+        let contiguous = N - end % N;
+        let padding = if contiguous < required { contiguous } else { 0 };
+        if N - (end - start) < padding + required {
+            return false;
+        }
+
+        if padding > 0 {
+            self.data[end % N] = 0;
+            end += padding;
+        }
+
+        let at = end % N;
+        self.data[at] = message.len() as u8;
+        self.data[at + 1..at + required].clone_from_slice(message);
+        self.end().store(end + required, Ordering::Release);
+
+        true
     }
 
-    fn message_at(&self, at: usize) -> Option<Vec<u8>> {
-        let length = *self.data.get(at)?;
-        if length == 0 {
-            return None;
+    // Announcements the reader hasn't picked up yet
+    #[cfg(test)]
+    pub(crate) fn pending_wrapping(&self) -> Vec<String> {
+        let mut messages = vec![];
+        let mut start = self.start().load(Ordering::Acquire);
+        let end = self.end().load(Ordering::Acquire);
+        while start < end {
+            if self.data[start % N] == 0 {
+                start += N - start % N;
+                continue;
+            }
+            let at = start % N;
+            let length = self.data[at] as usize;
+            messages
+                .push(String::from_utf8_lossy(&self.data[at + 1..at + length + 1]).into_owned());
+            start += length + 1;
         }
-        Some(self.data[at + 1..at + (length as usize) + 1].to_vec())
+        messages
+    }
+
+    pub(crate) fn can_push(&mut self, len: usize) -> bool {
+        frame::payload_at(self.end().load(Ordering::Relaxed), self.flags) + len <= N
+    }
+
+    pub(crate) fn is_done_reading(&self) -> bool {
+        self.done_reading.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn mark_done_writing(&self) {
+        self.done_writing.store(true, Ordering::Release);
     }
 
     pub(crate) fn messages(&self) -> Vec<String> {
+        let end = self.end().load(Ordering::Acquire);
         let mut messages = vec![];
         let mut i = 0;
-        while i < N {
-            if let Some(message) = self.message_at(i) {
-                i += message.len() + 1;
-                messages.push(String::from_utf8(message).unwrap());
-            } else {
-                break;
-            }
+        while i < end {
+            let (frame, next) = match frame::decode(&self.data, i, self.flags) {
+                Some(decoded) => decoded,
+                None => break,
+            };
+            messages.push(String::from_utf8_lossy(frame.payload).into_owned());
+            i = next;
         }
         messages
     }
@@ -0,0 +1,25 @@
+use crate::WriterDisconnectError;
+
+#[derive(Debug, Default)]
+pub struct ShutdownReport {
+    pub released: Vec<String>,
+    pub failed: Vec<WriterDisconnectError>,
+}
+
+impl ShutdownReport {
+    // Every step that has failed is reported, not just the first one
+    pub(crate) fn record(
+        &mut self,
+        segment: String,
+        result: Result<(), Vec<WriterDisconnectError>>,
+    ) {
+        match result {
+            Ok(()) => self.released.push(segment),
+            Err(failed) => self.failed.extend(failed),
+        }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.failed.is_empty()
+    }
+}